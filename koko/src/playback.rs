@@ -0,0 +1,154 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use kokoros::audio::resample::resample;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Plays mono audio on an output device as it is pushed
+pub struct Player {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    stream: cpal::Stream,
+    source_rate: u32,
+    device_rate: u32,
+}
+
+impl Player {
+    /// Open the output device called `device_name`, or the default one, preferring a
+    /// configuration that runs at `source_rate` so no resampling is needed
+    pub fn new(
+        source_rate: u32,
+        device_name: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let host = cpal::default_host();
+        let device = match device_name {
+            Some(name) => host
+                .output_devices()?
+                .find(|device| device.name().is_ok_and(|n| n == name))
+                .ok_or_else(|| {
+                    format!(
+                        "No output device named '{}', see `koko --list-devices`",
+                        name
+                    )
+                })?,
+            None => host
+                .default_output_device()
+                .ok_or("No audio output device available")?,
+        };
+
+        let native_rate = device
+            .supported_output_configs()?
+            .find(|range| {
+                range.min_sample_rate().0 <= source_rate && source_rate <= range.max_sample_rate().0
+            })
+            .map(|range| range.with_sample_rate(cpal::SampleRate(source_rate)));
+        let supported = match native_rate {
+            Some(config) => config,
+            None => device.default_output_config()?,
+        };
+
+        let sample_format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+        let device_rate = config.sample_rate.0;
+        if device_rate != source_rate {
+            tracing::info!(
+                "Output device runs at {} Hz, resampling from {} Hz",
+                device_rate,
+                source_rate
+            );
+        }
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone())?,
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone())?,
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone())?,
+            format => return Err(format!("Unsupported output sample format: {}", format).into()),
+        };
+        stream.play()?;
+
+        Ok(Player {
+            queue,
+            stream,
+            source_rate,
+            device_rate,
+        })
+    }
+
+    /// Queue mono samples at the source rate for playback
+    pub fn push(&self, samples: &[f32]) {
+        let samples = resample(samples, self.source_rate, self.device_rate);
+        self.queue.lock().unwrap().extend(samples);
+    }
+
+    /// Block until everything queued has been played
+    pub fn finish(self) {
+        while !self.queue.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // Let the device drain its own buffer before the stream is dropped
+        std::thread::sleep(Duration::from_millis(200));
+        drop(self.stream);
+    }
+}
+
+/// Print the output devices with the channel counts and sample rates they support
+pub fn list_devices() -> Result<(), Box<dyn std::error::Error>> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+    println!("Output devices ({}):", host.id().name());
+    println!("==========================================");
+    for device in host.output_devices()? {
+        let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+        let marker = if Some(&name) == default_name.as_ref() {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {}", marker, name);
+
+        match device.supported_output_configs() {
+            Ok(configs) => {
+                for range in configs {
+                    println!(
+                        "      {} ch, {}-{} Hz, {}",
+                        range.channels(),
+                        range.min_sample_rate().0,
+                        range.max_sample_rate().0,
+                        range.sample_format()
+                    );
+                }
+            }
+            Err(e) => println!("      (configurations unavailable: {})", e),
+        }
+    }
+    println!("==========================================");
+    println!("* Default device");
+    Ok(())
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut queue = queue.lock().unwrap();
+            // Duplicate the mono signal to every channel, padding with silence on underrun
+            for frame in data.chunks_mut(channels) {
+                let sample = queue.pop_front().unwrap_or(0.0);
+                for out in frame.iter_mut() {
+                    *out = T::from_sample(sample);
+                }
+            }
+        },
+        |err| tracing::error!("Audio output error: {}", err),
+        None,
+    )
+}
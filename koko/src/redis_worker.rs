@@ -0,0 +1,389 @@
+use kokoros::audio::AudioFormat;
+use kokoros::tts::instructions::speed_factor;
+use kokoros::tts::koko::{TTSKoko, TTSRawAudioOpts, style_voices};
+use redis::streams::{
+    StreamId, StreamInfoConsumer, StreamInfoConsumersReply, StreamReadOptions, StreamReadReply,
+};
+use redis::{Commands, Connection};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a read waits for new requests before checking the connection again
+const BLOCK_MS: usize = 5000;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Number of recent requests the queue wait is averaged over
+const WAIT_WINDOW: usize = 20;
+/// Minimum time between two queue wait warnings
+const WAIT_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+/// Consumers idle for longer without a request in hand are assumed to have stopped. Waiting
+/// workers touch the group every `BLOCK_MS`
+const ACTIVE_IDLE_MS: usize = 3 * BLOCK_MS;
+
+/// Where the worker reads requests from and publishes results to
+pub struct WorkerConfig<'a> {
+    pub url: &'a str,
+    pub stream: &'a str,
+    pub group: &'a str,
+    pub consumer: &'a str,
+    pub reply_stream: &'a str,
+    /// Write audio files in `format` here and publish their paths instead of the audio bytes
+    pub output_dir: Option<&'a str>,
+    pub channels: u16,
+    pub format: AudioFormat,
+    /// Warn when requests wait longer than this on average before a worker picks them up,
+    /// a sign that the group needs more workers
+    pub wait_warning: Option<Duration>,
+    /// Only these voices may be requested, alone or as part of a blend or morph. Any voice
+    /// is allowed when empty
+    pub allowed_voices: &'a [String],
+}
+
+/// The first voice in `style_name` that is not in `allowed`, if any
+pub fn disallowed_voice<'s>(style_name: &'s str, allowed: &[String]) -> Option<&'s str> {
+    if allowed.is_empty() {
+        return None;
+    }
+    style_voices(style_name)
+        .into_iter()
+        .find(|voice| !allowed.iter().any(|allowed| allowed == voice))
+}
+
+/// Consume synthesis requests from a Redis stream as part of a consumer group, forever.
+///
+/// Each entry needs a `text` field and may override `voice`, `speed` and `lan`, or name a
+/// `reply_to` stream. Free-form `instructions` like "speak slowly" adjust the speed, see
+/// [`speed_factor`]. The result is added to the reply stream with the request's `id` and
+/// either an `audio` field holding the WAV bytes, a `path` field, or an `error` field.
+/// Requests for voices outside `allowed_voices` are rejected before synthesis with an
+/// `error` field and a `status` of 403.
+pub fn run(
+    tts: &TTSKoko,
+    defaults: TTSRawAudioOpts,
+    config: &WorkerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = redis::Client::open(config.url)?;
+    let mut queue_wait = QueueWait::default();
+    loop {
+        let mut con = match connect(&client, config) {
+            Ok(con) => con,
+            Err(e) => {
+                tracing::warn!("Redis connection failed: {}, retrying", e);
+                std::thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+        tracing::info!(
+            "Waiting for requests on stream {} as {}/{}",
+            config.stream,
+            config.group,
+            config.consumer
+        );
+
+        if let Err(e) = serve(&mut con, tts, &defaults, config, &mut queue_wait) {
+            tracing::warn!("Redis connection lost: {}, reconnecting", e);
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+    }
+}
+
+fn connect(client: &redis::Client, config: &WorkerConfig) -> redis::RedisResult<Connection> {
+    let mut con = client.get_connection()?;
+    // Create the group on first use, starting with new entries only
+    let created: redis::RedisResult<()> =
+        con.xgroup_create_mkstream(config.stream, config.group, "$");
+    match created {
+        Err(e) if e.code() != Some("BUSYGROUP") => Err(e),
+        _ => Ok(con),
+    }
+}
+
+fn serve(
+    con: &mut Connection,
+    tts: &TTSKoko,
+    defaults: &TTSRawAudioOpts,
+    config: &WorkerConfig,
+    queue_wait: &mut QueueWait,
+) -> redis::RedisResult<()> {
+    // Answer requests this consumer was given but never acknowledged first, e.g. when the
+    // connection dropped mid-request, as reading `>` only returns new entries
+    let pending_options = StreamReadOptions::default()
+        .group(config.group, config.consumer)
+        .count(1);
+    loop {
+        let reply: StreamReadReply =
+            con.xread_options(&[config.stream], &["0"], &pending_options)?;
+        let entries: Vec<StreamId> = reply.keys.into_iter().flat_map(|key| key.ids).collect();
+        if entries.is_empty() {
+            break;
+        }
+        for entry in entries {
+            tracing::info!("Answering request {} left from before", entry.id);
+            respond(con, tts, defaults, config, &entry)?;
+        }
+    }
+
+    let read_options = StreamReadOptions::default()
+        .group(config.group, config.consumer)
+        .count(1)
+        .block(BLOCK_MS);
+
+    loop {
+        let reply: StreamReadReply = con.xread_options(&[config.stream], &[">"], &read_options)?;
+        for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+            if let Some(threshold) = config.wait_warning {
+                check_queue_wait(con, config, queue_wait, &entry.id, threshold);
+            }
+            respond(con, tts, defaults, config, &entry)?;
+        }
+    }
+}
+
+/// Synthesize one request, add the result to its reply stream and acknowledge it
+fn respond(
+    con: &mut Connection,
+    tts: &TTSKoko,
+    defaults: &TTSRawAudioOpts,
+    config: &WorkerConfig,
+    entry: &StreamId,
+) -> redis::RedisResult<()> {
+    let reply_to = entry
+        .get::<String>("reply_to")
+        .unwrap_or_else(|| config.reply_stream.to_string());
+
+    let mut result = redis::cmd("XADD");
+    result.arg(&reply_to).arg("*").arg("id").arg(&entry.id);
+    match handle(entry, tts, defaults, config) {
+        Ok(Output::Audio(bytes)) => result.arg("audio").arg(bytes),
+        Ok(Output::Path(path)) => result.arg("path").arg(path),
+        Ok(Output::Forbidden(voice)) => {
+            tracing::warn!("Request {} asked for disallowed voice {}", entry.id, voice);
+            let error = format!("voice {} is not allowed", voice);
+            result.arg("error").arg(error).arg("status").arg(403)
+        }
+        Err(e) => {
+            tracing::error!("Request {} failed: {}", entry.id, e);
+            result.arg("error").arg(e.to_string())
+        }
+    };
+    result.query::<()>(con)?;
+    con.xack::<_, _, _, ()>(config.stream, config.group, &[&entry.id])
+}
+
+/// Time requests spent in the stream before being picked up, over the last `WAIT_WINDOW`
+#[derive(Default)]
+struct QueueWait {
+    recent: VecDeque<Duration>,
+    last_warning: Option<Instant>,
+}
+
+impl QueueWait {
+    /// Record a request's wait, returning the average once it exceeds `threshold` over a
+    /// full window and no warning was given within `WAIT_WARNING_INTERVAL`
+    fn record(&mut self, wait: Duration, threshold: Duration) -> Option<Duration> {
+        if self.recent.len() == WAIT_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(wait);
+        let average = self.recent.iter().sum::<Duration>() / self.recent.len() as u32;
+
+        let due = self
+            .last_warning
+            .is_none_or(|warned| warned.elapsed() >= WAIT_WARNING_INTERVAL);
+        if self.recent.len() < WAIT_WINDOW || average <= threshold || !due {
+            return None;
+        }
+        self.last_warning = Some(Instant::now());
+        Some(average)
+    }
+}
+
+/// How long ago the entry was added, from the millisecond timestamp in its ID
+fn entry_age(id: &str) -> Option<Duration> {
+    let added_ms: u64 = id.split_once('-')?.0.parse().ok()?;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_millis() as u64;
+    // Saturates when the Redis server's clock is ahead of ours
+    Some(Duration::from_millis(now_ms.saturating_sub(added_ms)))
+}
+
+/// Record how long the request waited and warn, with the number of active workers, when
+/// requests keep waiting longer than `threshold`
+fn check_queue_wait(
+    con: &mut Connection,
+    config: &WorkerConfig,
+    queue_wait: &mut QueueWait,
+    id: &str,
+    threshold: Duration,
+) {
+    let Some(wait) = entry_age(id) else {
+        return;
+    };
+    tracing::debug!("Request {} waited {:?} in the queue", id, wait);
+    let Some(average) = queue_wait.record(wait, threshold) else {
+        return;
+    };
+
+    let workers = con
+        .xinfo_consumers::<_, _, StreamInfoConsumersReply>(config.stream, config.group)
+        .map(|reply| active_workers(&reply.consumers).to_string())
+        .unwrap_or_else(|_| "an unknown number of".to_string());
+    tracing::warn!(
+        "Requests on {} waited {:.1?} on average before being picked up, with {} workers in group {}. Consider starting more workers",
+        config.stream,
+        average,
+        workers,
+        config.group
+    );
+}
+
+/// Consumers that are waiting for requests or working on one. A worker busy with a long
+/// synthesis does not touch the group, but holds the request as pending
+fn active_workers(consumers: &[StreamInfoConsumer]) -> usize {
+    consumers
+        .iter()
+        .filter(|c| c.idle <= ACTIVE_IDLE_MS || c.pending > 0)
+        .count()
+}
+
+enum Output {
+    Audio(Vec<u8>),
+    Path(String),
+    /// The request named this voice, which is not allowed
+    Forbidden(String),
+}
+
+fn handle(
+    entry: &StreamId,
+    tts: &TTSKoko,
+    defaults: &TTSRawAudioOpts,
+    config: &WorkerConfig,
+) -> Result<Output, Box<dyn std::error::Error>> {
+    let text: String = entry.get("text").ok_or("request has no text field")?;
+    let voice: Option<String> = entry.get("voice");
+    let lan: Option<String> = entry.get("lan");
+    let style_name = voice.as_deref().unwrap_or(defaults.style_name);
+    let speed = match entry.get::<String>("speed") {
+        Some(speed) => Some(
+            speed
+                .parse()
+                .map_err(|_| format!("invalid speed: {}", speed))?,
+        ),
+        None => defaults.speed,
+    };
+    let speed = match entry.get::<String>("instructions") {
+        Some(instructions) => {
+            let speed = speed.unwrap_or_else(|| tts.voice_speed(style_name));
+            Some(speed * speed_factor(&instructions))
+        }
+        None => speed,
+    };
+
+    if let Some(voice) = disallowed_voice(style_name, config.allowed_voices) {
+        return Ok(Output::Forbidden(voice.to_string()));
+    }
+
+    let opts = TTSRawAudioOpts {
+        txt: &text,
+        lan: lan.as_deref().unwrap_or(defaults.lan),
+        style_name,
+        speed,
+        request_id: Some(&entry.id),
+        ..defaults.clone()
+    };
+    let audio = tts.tts_wav_bytes(opts, config.channels, config.format)?;
+
+    match config.output_dir {
+        Some(dir) => {
+            // Entry IDs look like `1700000000000-0`, which is safe as a file name
+            let path = Path::new(dir).join(format!("{}.{}", entry.id, config.format.extension()));
+            std::fs::write(&path, audio)?;
+            Ok(Output::Path(path.display().to_string()))
+        }
+        None => Ok(Output::Audio(audio)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_queue_wait_window() {
+        let mut queue_wait = QueueWait::default();
+        // No warning until a full window has been seen
+        for _ in 0..WAIT_WINDOW - 1 {
+            assert_eq!(queue_wait.record(Duration::from_secs(10), THRESHOLD), None);
+        }
+        assert_eq!(
+            queue_wait.record(Duration::from_secs(10), THRESHOLD),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(queue_wait.recent.len(), WAIT_WINDOW);
+    }
+
+    #[test]
+    fn test_queue_wait_threshold() {
+        let mut queue_wait = QueueWait::default();
+        // Waiting exactly as long as the threshold is fine
+        for _ in 0..WAIT_WINDOW {
+            assert_eq!(queue_wait.record(THRESHOLD, THRESHOLD), None);
+        }
+        // The oldest wait drops out of the average as a longer one comes in
+        assert_eq!(
+            queue_wait.record(THRESHOLD * 2, THRESHOLD),
+            Some(Duration::from_millis(5250))
+        );
+    }
+
+    #[test]
+    fn test_queue_wait_rate_limit() {
+        let mut queue_wait = QueueWait::default();
+        let long = Duration::from_secs(10);
+        for _ in 0..WAIT_WINDOW - 1 {
+            queue_wait.record(long, THRESHOLD);
+        }
+        assert!(queue_wait.record(long, THRESHOLD).is_some());
+        assert_eq!(queue_wait.record(long, THRESHOLD), None);
+
+        queue_wait.last_warning = Some(Instant::now() - WAIT_WARNING_INTERVAL);
+        assert!(queue_wait.record(long, THRESHOLD).is_some());
+    }
+
+    #[test]
+    fn test_entry_age() {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let age = entry_age(&format!("{}-0", now_ms - 3000)).unwrap();
+        assert!(age >= Duration::from_millis(3000) && age < Duration::from_millis(4000));
+        // IDs from a clock ahead of ours count as no wait
+        assert_eq!(
+            entry_age(&format!("{}-3", now_ms + 60_000)),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(entry_age("not-an-id"), None);
+        assert_eq!(entry_age("1700000000000"), None);
+    }
+
+    #[test]
+    fn test_active_workers() {
+        let consumer = |idle, pending| StreamInfoConsumer {
+            idle,
+            pending,
+            ..StreamInfoConsumer::default()
+        };
+        let consumers = [
+            consumer(100, 0),
+            consumer(ACTIVE_IDLE_MS + 1, 0),
+            // Busy with a long synthesis
+            consumer(10 * ACTIVE_IDLE_MS, 1),
+        ];
+        assert_eq!(active_workers(&consumers), 2);
+    }
+}
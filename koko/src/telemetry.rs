@@ -0,0 +1,43 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Keeps the OTLP exporter alive and flushes pending spans when dropped
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to flush OpenTelemetry spans: {}", e);
+        }
+    }
+}
+
+/// Build a tracing layer exporting spans over OTLP/HTTP to `endpoint`,
+/// e.g. `http://localhost:4318/v1/traces`
+pub fn otlp_layer<S>(
+    endpoint: &str,
+) -> Result<(OpenTelemetryLayer<S, Tracer>, Telemetry), Box<dyn std::error::Error>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("koko").build())
+        .build();
+    let tracer = provider.tracer("koko");
+
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        Telemetry { provider },
+    ))
+}
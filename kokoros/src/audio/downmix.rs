@@ -0,0 +1,35 @@
+//! Mixing multichannel audio down to mono.
+
+/// Mix interleaved `channels`-channel audio down to mono with equal-power compensation: the
+/// channels are summed and scaled by `1 / sqrt(channels)`, -3 dB for stereo. This keeps
+/// panned and decorrelated material at the same loudness, and raises content shared by both
+/// channels by 3 dB where a plain sum of left and right would raise it by 6 dB and clip.
+///
+/// Speech is synthesized in mono and copied to every output channel, so this is only
+/// needed for audio that has had stereo processing applied; `--mono` output never does.
+pub fn downmix_mono(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    let gain = (channels as f32).sqrt().recip();
+    samples
+        .chunks(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() * gain)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_mono() {
+        assert_eq!(downmix_mono(&[0.5, -0.5], 1), vec![0.5, -0.5]);
+
+        // Hard-panned left, then centered
+        let mono = downmix_mono(&[0.8, 0.0, 0.5, 0.5], 2);
+        assert!((mono[0] - 0.8 / 2f32.sqrt()).abs() < 1e-6);
+        assert!((mono[1] - 1.0 / 2f32.sqrt()).abs() < 1e-6);
+        assert_eq!(downmix_mono(&[0.1, 0.2, 0.3, 0.0, 0.0, 0.0], 3).len(), 2);
+    }
+}
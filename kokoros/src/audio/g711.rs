@@ -0,0 +1,201 @@
+//! G.711 mu-law and A-law companding as used by telephony systems.
+
+use super::quantize::Quantization;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Sample rate of G.711 telephony audio
+pub const SAMPLE_RATE: u32 = 8000;
+
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 32635;
+
+/// Upper bounds of the A-law segments for 13-bit input
+const ALAW_SEGMENT_END: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Law {
+    MuLaw,
+    ALaw,
+}
+
+impl Law {
+    /// `wFormatTag` of this encoding in a WAV `fmt ` chunk
+    fn wav_format_tag(self) -> u16 {
+        match self {
+            Law::ALaw => 6,
+            Law::MuLaw => 7,
+        }
+    }
+
+    pub fn encode(self, sample: i16) -> u8 {
+        match self {
+            Law::MuLaw => linear_to_ulaw(sample),
+            Law::ALaw => linear_to_alaw(sample),
+        }
+    }
+}
+
+pub fn linear_to_ulaw(sample: i16) -> u8 {
+    let mut pcm = sample as i32;
+    let sign = if pcm < 0 {
+        pcm = -pcm;
+        0x80
+    } else {
+        0
+    };
+    pcm = pcm.min(ULAW_CLIP) + ULAW_BIAS;
+
+    let exponent = 7 - ((pcm >> 7) as u8).leading_zeros() as i32;
+    let mantissa = (pcm >> (exponent + 3)) & 0x0F;
+    !(sign | (exponent << 4) | mantissa) as u8
+}
+
+pub fn ulaw_to_linear(value: u8) -> i16 {
+    let value = !value;
+    let exponent = ((value >> 4) & 0x07) as i32;
+    let mantissa = (value & 0x0F) as i32;
+    let magnitude = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+    if value & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    let mut pcm = (sample as i32) >> 3;
+    let mask = if pcm >= 0 {
+        0xD5
+    } else {
+        pcm = -pcm - 1;
+        0x55
+    };
+
+    let Some(segment) = ALAW_SEGMENT_END.iter().position(|&end| pcm <= end) else {
+        return (0x7F ^ mask) as u8;
+    };
+    let quantized = if segment < 2 {
+        (pcm >> 1) & 0x0F
+    } else {
+        (pcm >> segment) & 0x0F
+    };
+    (((segment as i32) << 4 | quantized) ^ mask) as u8
+}
+
+pub fn alaw_to_linear(value: u8) -> i16 {
+    let value = value ^ 0x55;
+    let mut magnitude = ((value & 0x0F) as i32) << 4;
+    let segment = ((value & 0x70) >> 4) as i32;
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+    if value & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+/// Encode float samples in `[-1.0, 1.0]` to G.711 bytes
+pub fn encode(samples: &[f32], law: Law, quantization: Quantization) -> Vec<u8> {
+    quantization
+        .to_i16(samples)
+        .into_iter()
+        .map(|s| law.encode(s))
+        .collect()
+}
+
+/// Write 8 kHz mono G.711 data, either as headerless bytes or inside a WAV container
+pub fn write(
+    path: &str,
+    encoded: &[u8],
+    law: Law,
+    wav_container: bool,
+) -> Result<(), std::io::Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_to(&mut out, encoded, law, wav_container)?;
+    out.flush()
+}
+
+/// Same as [`write`], but to any writer
+pub fn write_to<W: Write>(
+    out: &mut W,
+    encoded: &[u8],
+    law: Law,
+    wav_container: bool,
+) -> Result<(), std::io::Error> {
+    if wav_container {
+        let data_len = encoded.len() as u32;
+        // Non-PCM formats carry an 18 byte `fmt ` chunk and a `fact` chunk
+        let riff_len = 4 + (8 + 18) + (8 + 4) + (8 + data_len + data_len % 2);
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&riff_len.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&18u32.to_le_bytes())?;
+        out.write_all(&law.wav_format_tag().to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // channels
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?; // byte rate
+        out.write_all(&1u16.to_le_bytes())?; // block align
+        out.write_all(&8u16.to_le_bytes())?; // bits per sample
+        out.write_all(&0u16.to_le_bytes())?; // extension size
+
+        out.write_all(b"fact")?;
+        out.write_all(&4u32.to_le_bytes())?;
+        out.write_all(&data_len.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&data_len.to_le_bytes())?;
+        out.write_all(encoded)?;
+        if data_len % 2 == 1 {
+            out.write_all(&[0])?;
+        }
+    } else {
+        out.write_all(encoded)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_g711_known_values() {
+        assert_eq!(linear_to_ulaw(0), 0xFF);
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xAA);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+    }
+
+    #[test]
+    fn test_g711_round_trip() {
+        for sample in (i16::MIN..=i16::MAX).step_by(97) {
+            for (law, decode) in [
+                (Law::MuLaw, ulaw_to_linear as fn(u8) -> i16),
+                (Law::ALaw, alaw_to_linear),
+            ] {
+                let decoded = decode(law.encode(sample)) as i32;
+                let error = (decoded - sample as i32).abs();
+                // Companding keeps the error roughly proportional to the magnitude
+                let tolerance = (sample as i32).abs() / 16 + 40;
+                assert!(
+                    error <= tolerance,
+                    "{:?}: {} -> {} (error {})",
+                    law,
+                    sample,
+                    decoded,
+                    error
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/// Peak and RMS level of audio, accumulated chunk by chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Levels {
+    /// Number of samples seen
+    pub samples: usize,
+    /// Largest absolute sample value
+    pub peak: f32,
+    sum_squares: f64,
+}
+
+impl Levels {
+    pub fn of(samples: &[f32]) -> Self {
+        let mut levels = Self::default();
+        levels.add(samples);
+        levels
+    }
+
+    /// Take `samples` into account, as if appended to the audio measured so far
+    pub fn add(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.peak = self.peak.max(sample.abs());
+            self.sum_squares += sample as f64 * sample as f64;
+        }
+        self.samples += samples.len();
+    }
+
+    pub fn duration_secs(&self, sample_rate: u32) -> f64 {
+        self.samples as f64 / sample_rate as f64
+    }
+
+    pub fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.sum_squares / self.samples as f64).sqrt() as f32
+    }
+
+    pub fn peak_dbfs(&self) -> f32 {
+        to_dbfs(self.peak)
+    }
+
+    pub fn rms_dbfs(&self) -> f32 {
+        to_dbfs(self.rms())
+    }
+}
+
+/// Amplitude relative to full scale in decibels, negative infinity for silence
+pub fn to_dbfs(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let mut levels = Levels::of(&[0.5, -0.5]);
+        levels.add(&[-1.0, 0.0]);
+        assert_eq!(levels.samples, 4);
+        assert_eq!(levels.peak, 1.0);
+        assert_eq!(levels.peak_dbfs(), 0.0);
+        assert!((levels.rms() - (1.5f32 / 4.0).sqrt()).abs() < 1e-6);
+        assert_eq!(Levels::default().rms_dbfs(), f32::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,71 @@
+pub mod downmix;
+pub mod g711;
+pub mod level;
+pub mod ogg_flac;
+pub mod quantize;
+pub mod resample;
+pub mod silence;
+pub mod wav_f16;
+pub mod wav_stream;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Encoding of the audio written to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioFormat {
+    /// 32-bit float WAV at the model's sample rate
+    #[default]
+    Wav,
+    /// 16-bit float WAV at the model's sample rate, see [`wav_f16`]
+    WavF16,
+    /// 16-bit FLAC in an Ogg container at the model's sample rate, see [`ogg_flac`]
+    OggFlac,
+    /// 8 kHz mono G.711 mu-law
+    Ulaw,
+    /// 8 kHz mono G.711 A-law
+    Alaw,
+}
+
+impl AudioFormat {
+    /// File extension for audio encoded by
+    /// [`TTSKoko::encode_audio`](crate::tts::koko::TTSKoko::encode_audio), which wraps
+    /// G.711 in a WAV container
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::OggFlac => "oga",
+            AudioFormat::Wav | AudioFormat::WavF16 | AudioFormat::Ulaw | AudioFormat::Alaw => "wav",
+        }
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Ok(AudioFormat::Wav),
+            "wav-f16" | "f16" => Ok(AudioFormat::WavF16),
+            "ogg-flac" | "oga" => Ok(AudioFormat::OggFlac),
+            "ulaw" | "mulaw" | "mu-law" => Ok(AudioFormat::Ulaw),
+            "alaw" | "a-law" => Ok(AudioFormat::Alaw),
+            _ => Err(format!(
+                "unknown audio format '{}', expected one of: wav, wav-f16, ogg-flac, ulaw, alaw",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::WavF16 => "wav-f16",
+            AudioFormat::OggFlac => "ogg-flac",
+            AudioFormat::Ulaw => "ulaw",
+            AudioFormat::Alaw => "alaw",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,140 @@
+//! Lossless FLAC audio in an Ogg container, which browsers can play while it downloads.
+//!
+//! Follows the Ogg FLAC mapping: a first packet carrying the STREAMINFO block, one packet
+//! with a VORBIS_COMMENT block, then one packet per FLAC frame.
+
+use super::quantize::Quantization;
+use flacenc::bitsink::ByteSink;
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::Write;
+
+/// Serial number of the only logical stream
+const SERIAL: u32 = 1;
+
+const BITS_PER_SAMPLE: usize = 16;
+
+/// Written as the encoder of the stream's comment block
+const VENDOR: &str = concat!("kokoros ", env!("CARGO_PKG_VERSION"));
+
+/// Encode float samples as 16-bit FLAC in an Ogg container, copying each sample into
+/// `channels` channels
+pub fn write_to<W: Write>(
+    out: &mut W,
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    quantization: Quantization,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let interleaved: Vec<i32> = quantization
+        .to_i16(samples)
+        .into_iter()
+        .flat_map(|sample| std::iter::repeat_n(sample as i32, channels as usize))
+        .collect();
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| format!("invalid FLAC encoder settings: {:?}", e))?;
+    let source = flacenc::source::MemSource::from_samples(
+        &interleaved,
+        channels as usize,
+        BITS_PER_SAMPLE,
+        sample_rate as usize,
+    );
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| format!("FLAC encoding failed: {:?}", e))?;
+
+    let mut ogg = PacketWriter::new(out);
+    ogg.write_packet(
+        mapping_header(&stream)?,
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+    ogg.write_packet(comment_header(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+    let frame_count = stream.frame_count();
+    let mut granule = 0;
+    for i in 0..frame_count {
+        let frame = stream
+            .frame(i)
+            .ok_or("FLAC frame missing from the stream")?;
+        let mut sink = ByteSink::new();
+        frame
+            .write(&mut sink)
+            .map_err(|e| format!("FLAC encoding failed: {:?}", e))?;
+        granule += frame.block_size() as u64;
+        let end = match i + 1 == frame_count {
+            true => PacketWriteEndInfo::EndStream,
+            false => PacketWriteEndInfo::NormalPacket,
+        };
+        ogg.write_packet(sink.into_inner(), SERIAL, end, granule)?;
+    }
+    Ok(())
+}
+
+/// The first packet: the mapping's signature and version, the number of header packets
+/// after it, then the native FLAC signature and STREAMINFO block
+fn mapping_header(stream: &flacenc::component::Stream) -> Result<Vec<u8>, String> {
+    let mut stream_info = ByteSink::new();
+    stream
+        .stream_info()
+        .write(&mut stream_info)
+        .map_err(|e| format!("FLAC encoding failed: {:?}", e))?;
+    let stream_info = stream_info.into_inner();
+
+    let mut packet = vec![0x7F];
+    packet.extend_from_slice(b"FLAC");
+    packet.extend_from_slice(&[1, 0]); // mapping version 1.0
+    packet.extend_from_slice(&1u16.to_be_bytes()); // header packets that follow
+    packet.extend_from_slice(b"fLaC");
+    packet.push(0); // STREAMINFO, not the last metadata block
+    packet.extend_from_slice(&(stream_info.len() as u32).to_be_bytes()[1..]);
+    packet.extend(stream_info);
+    Ok(packet)
+}
+
+/// The VORBIS_COMMENT block the mapping requires, naming only the encoder
+fn comment_header() -> Vec<u8> {
+    let mut comment = Vec::new();
+    comment.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    comment.extend_from_slice(VENDOR.as_bytes());
+    comment.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+
+    let mut packet = vec![0x80 | 4]; // VORBIS_COMMENT, the last metadata block
+    packet.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..]);
+    packet.extend(comment);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ogg_flac_headers() {
+        let samples: Vec<f32> = (0..10_000).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let mut ogg = Vec::new();
+        write_to(&mut ogg, &samples, 24000, 1, Quantization::default()).unwrap();
+
+        assert_eq!(&ogg[..4], b"OggS");
+        // A lone 51 byte packet on the first page, after the 27 byte header and one lacing value
+        assert_eq!(ogg[26], 1);
+        assert_eq!(ogg[27], 51);
+        assert_eq!(&ogg[28..33], b"\x7FFLAC");
+        assert_eq!(&ogg[37..41], b"fLaC");
+        // Total samples in STREAMINFO
+        let info = &ogg[45..79];
+        let total = u64::from_be_bytes([
+            0,
+            0,
+            0,
+            info[13] & 0x0F,
+            info[14],
+            info[15],
+            info[16],
+            info[17],
+        ]);
+        assert_eq!(total, samples.len() as u64);
+    }
+}
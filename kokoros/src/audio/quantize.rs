@@ -0,0 +1,159 @@
+//! Conversion of float samples to 16-bit integers for the integer output formats.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What happens to samples beyond full scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clipping {
+    /// Cut samples off at full scale
+    #[default]
+    Hard,
+    /// Compress samples above [`SOFT_KNEE`] smoothly towards full scale, which sounds less
+    /// harsh than hard clipping but also lowers peaks that would not have clipped
+    Soft,
+}
+
+impl FromStr for Clipping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hard" => Ok(Clipping::Hard),
+            "soft" => Ok(Clipping::Soft),
+            _ => Err(format!(
+                "unknown clipping '{}', expected one of: hard, soft",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Clipping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Clipping::Hard => "hard",
+            Clipping::Soft => "soft",
+        })
+    }
+}
+
+impl Clipping {
+    /// Bring `sample` within full scale, for outputs that keep float samples but cannot
+    /// represent louder ones, such as MP3
+    pub fn clip(self, sample: f32) -> f32 {
+        match self {
+            Clipping::Hard => sample.clamp(-1.0, 1.0),
+            Clipping::Soft => soft_clip(sample),
+        }
+    }
+}
+
+/// Level above which [`Clipping::Soft`] starts compressing
+pub const SOFT_KNEE: f32 = 0.8;
+
+/// How float samples are converted to 16-bit integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quantization {
+    /// Add triangular (TPDF) dither of one step, which turns the distortion rounding adds
+    /// to quiet passages into a constant, much less audible noise floor. The dither is
+    /// pseudo-random with a fixed seed, so the same audio always converts the same way
+    pub dither: bool,
+    pub clipping: Clipping,
+}
+
+impl Quantization {
+    pub fn to_i16(&self, samples: &[f32]) -> Vec<i16> {
+        let mut dither = Dither::default();
+        samples
+            .iter()
+            .map(|&sample| {
+                let sample = match self.clipping {
+                    Clipping::Hard => sample,
+                    Clipping::Soft => soft_clip(sample),
+                };
+                let mut scaled = sample * i16::MAX as f32;
+                if self.dither {
+                    scaled += dither.next();
+                }
+                // Rounds to the nearest step and saturates at full scale
+                scaled.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect()
+    }
+}
+
+/// Leave samples up to [`SOFT_KNEE`] alone and approach full scale asymptotically above it,
+/// with no corner at the knee
+fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_KNEE;
+    let compressed = SOFT_KNEE + headroom * ((magnitude - SOFT_KNEE) / headroom).tanh();
+    compressed.copysign(sample)
+}
+
+/// Triangular noise in (-1, 1) steps, from the difference of two uniform values
+struct Dither {
+    state: u32,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self { state: 0x9E37_79B9 }
+    }
+}
+
+impl Dither {
+    fn next(&mut self) -> f32 {
+        self.uniform() - self.uniform()
+    }
+
+    /// xorshift32, uniform in [0, 1)
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantization() {
+        let plain = Quantization::default();
+        assert_eq!(
+            plain.to_i16(&[0.0, 0.5, -1.0, 2.0, -2.0]),
+            vec![0, 16384, -32767, 32767, -32768]
+        );
+
+        let soft = Quantization {
+            clipping: Clipping::Soft,
+            ..plain
+        };
+        let soft_samples = soft.to_i16(&[0.5, 0.9, 2.0, -2.0]);
+        assert_eq!(soft_samples[0], 16384);
+        assert!(soft_samples[1] < plain.to_i16(&[0.9])[0]);
+        assert!(soft_samples[2] > 32000 && soft_samples[3] < -32000);
+        assert!(soft_clip(0.9) < soft_clip(1.0) && soft_clip(5.0) <= 1.0);
+        assert_eq!(Clipping::Hard.clip(-2.0), -1.0);
+        assert_eq!(Clipping::Hard.clip(0.9), 0.9);
+        assert_eq!(Clipping::Soft.clip(0.9), soft_clip(0.9));
+
+        // Dither moves a sample by at most one step, the same way every time
+        let dithered = Quantization {
+            dither: true,
+            ..plain
+        };
+        let quiet = vec![0.3 / i16::MAX as f32; 1000];
+        let samples = dithered.to_i16(&quiet);
+        assert!(samples.iter().all(|&s| (-1..=1).contains(&s)));
+        assert!(samples.iter().any(|&s| s != 0));
+        assert_eq!(samples, dithered.to_i16(&quiet));
+    }
+}
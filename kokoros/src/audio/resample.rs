@@ -0,0 +1,74 @@
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc kernel on each side of the interpolation point
+const HALF_TAPS: f64 = 16.0;
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Resamples mono audio from `from_rate` to `to_rate` Hz using Hann-windowed sinc
+/// interpolation. When downsampling, the kernel is widened so it also acts as the
+/// anti-aliasing low-pass filter at the new Nyquist frequency.
+pub fn resample(input: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || input.is_empty() {
+        return input.to_vec();
+    }
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    // Cutoff relative to the input Nyquist frequency
+    let cutoff = ratio.min(1.0);
+    let half_width = HALF_TAPS / cutoff;
+    let out_len = (input.len() as f64 * ratio).round() as usize;
+    let last = input.len() as isize - 1;
+
+    (0..out_len)
+        .map(|n| {
+            let t = n as f64 / ratio;
+            let first = ((t - half_width).ceil() as isize).max(0);
+            let end = ((t + half_width).floor() as isize).min(last);
+
+            let mut acc = 0.0;
+            for i in first..=end {
+                let x = t - i as f64;
+                let window = 0.5 * (1.0 + (PI * x / half_width).cos());
+                acc += input[i as usize] as f64 * cutoff * sinc(cutoff * x) * window;
+            }
+            acc as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * freq * i as f64 / rate as f64).sin() as f32 * 0.5)
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_resample() {
+        assert_eq!(resample(&[0.1, 0.2], 24000, 24000), vec![0.1, 0.2]);
+
+        // A tone below the new Nyquist frequency passes through
+        let passband = resample(&sine(1000.0, 24000, 24000), 24000, 8000);
+        assert_eq!(passband.len(), 8000);
+        let level = rms(&passband[100..7900]);
+        assert!((level - 0.5 / 2f32.sqrt()).abs() < 0.01, "rms {}", level);
+
+        // A tone above it is filtered out instead of aliasing
+        let stopband = resample(&sine(6000.0, 24000, 24000), 24000, 8000);
+        assert!(rms(&stopband[100..7900]) < 0.01);
+    }
+}
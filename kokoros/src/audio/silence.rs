@@ -0,0 +1,157 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of the RMS analysis window
+const WINDOW_MS: u32 = 10;
+
+/// Which silences `trim_silence` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrimMode {
+    /// Only leading and trailing silence
+    #[default]
+    Edges,
+    /// Leading and trailing silence, and internal pauses longer than the minimum
+    All,
+}
+
+impl FromStr for TrimMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "edges" => Ok(TrimMode::Edges),
+            "all" => Ok(TrimMode::All),
+            _ => Err(format!(
+                "unknown trim mode '{}', expected one of: edges, all",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TrimMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TrimMode::Edges => "edges",
+            TrimMode::All => "all",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceOptions {
+    pub mode: TrimMode,
+    /// Windows whose RMS level is below this many dBFS count as silent
+    pub threshold_db: f32,
+    /// Silent stretches shorter than this are left alone. Internal pauses longer than
+    /// this are shortened to it when collapsing
+    pub min_silence_ms: u32,
+}
+
+impl Default for SilenceOptions {
+    fn default() -> Self {
+        Self {
+            mode: TrimMode::Edges,
+            threshold_db: -50.0,
+            min_silence_ms: 300,
+        }
+    }
+}
+
+/// Remove silence from mono audio, detected over 10 ms RMS windows so isolated quiet
+/// samples inside speech do not count as silence.
+///
+/// Leading and trailing silence is removed entirely once it lasts at least
+/// `min_silence_ms`; with [`TrimMode::All`] longer internal pauses are collapsed to
+/// `min_silence_ms` as well.
+pub fn trim_silence(samples: &[f32], sample_rate: u32, opts: &SilenceOptions) -> Vec<f32> {
+    let window = (sample_rate * WINDOW_MS / 1000).max(1) as usize;
+    let threshold = 10f32.powf(opts.threshold_db / 20.0);
+    let silent: Vec<bool> = samples
+        .chunks(window)
+        .map(|w| (w.iter().map(|s| s * s).sum::<f32>() / w.len() as f32).sqrt() < threshold)
+        .collect();
+
+    let min_windows = (opts.min_silence_ms / WINDOW_MS).max(1) as usize;
+    let mut output = Vec::with_capacity(samples.len());
+    let mut start = 0;
+    while start < silent.len() {
+        let mut end = start;
+        while end < silent.len() && silent[end] == silent[start] {
+            end += 1;
+        }
+
+        let range = start * window..(end * window).min(samples.len());
+        let keep = if !silent[start] || end - start < min_windows {
+            range
+        } else if start == 0 || end == silent.len() {
+            range.start..range.start
+        } else if opts.mode == TrimMode::All {
+            range.start..range.start + min_windows * window
+        } else {
+            range
+        };
+        output.extend_from_slice(&samples[keep]);
+        start = end;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    fn tone(ms: usize) -> Vec<f32> {
+        (0..ms).map(|i| 0.5 * (i as f32 * 0.7).sin()).collect()
+    }
+
+    fn silence(ms: usize) -> Vec<f32> {
+        vec![0.0; ms]
+    }
+
+    #[test]
+    fn test_trim_silence() {
+        // 1 kHz keeps one sample per millisecond
+        let signal = [
+            silence(500),
+            tone(200),
+            silence(2000),
+            tone(200),
+            silence(40),
+        ]
+        .concat();
+
+        let edges = trim_silence(&signal, RATE, &SilenceOptions::default());
+        // The trailing 40 ms is shorter than the minimum, so only the lead-in goes
+        assert_eq!(edges.len(), 200 + 2000 + 200 + 40);
+
+        let all = trim_silence(
+            &signal,
+            RATE,
+            &SilenceOptions {
+                mode: TrimMode::All,
+                min_silence_ms: 100,
+                ..SilenceOptions::default()
+            },
+        );
+        assert_eq!(all.len(), 200 + 100 + 200 + 40);
+        assert_eq!(&all[..200], &signal[500..700]);
+
+        // A single near-zero sample inside speech is not silence
+        let mut speech = tone(300);
+        speech[150] = 0.0;
+        assert_eq!(
+            trim_silence(&speech, RATE, &SilenceOptions::default()),
+            speech
+        );
+    }
+
+    #[test]
+    fn test_trim_mode_from_str() {
+        assert_eq!("ALL".parse::<TrimMode>(), Ok(TrimMode::All));
+        assert!("middle".parse::<TrimMode>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! 32-bit float WAV written to a stream that cannot seek back, such as a pipe.
+//!
+//! A WAV header starts with the lengths of the file and of its samples, which are unknown
+//! until the last chunk is synthesized. Seekable files are written with `hound`, whose
+//! `finalize` goes back and fills them in. On a pipe both lengths are written as
+//! [`UNKNOWN_LEN`] up front, the convention ffmpeg, sox and most players follow to read
+//! the samples until the end of the stream.
+
+use std::io::Write;
+
+/// Placeholder for the RIFF and `data` chunk lengths of a stream of unknown length
+pub const UNKNOWN_LEN: u32 = u32::MAX;
+
+/// `wFormatTag` of IEEE float samples
+const IEEE_FLOAT: u16 = 3;
+
+/// Write the header of a WAV stream of 32-bit float samples
+pub fn write_header<W: Write>(out: &mut W, sample_rate: u32, channels: u16) -> std::io::Result<()> {
+    let block_align = channels * 4;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&UNKNOWN_LEN.to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&IEEE_FLOAT.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?; // byte rate
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&32u16.to_le_bytes())?; // bits per sample
+
+    out.write_all(b"data")?;
+    out.write_all(&UNKNOWN_LEN.to_le_bytes())
+}
+
+/// Write mono samples after [`write_header`], copying each into `channels` channels
+pub fn write_samples<W: Write>(out: &mut W, samples: &[f32], channels: u16) -> std::io::Result<()> {
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|&sample| std::iter::repeat_n(sample.to_le_bytes(), channels as usize).flatten())
+        .collect();
+    out.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_stream() {
+        let mut wav = Vec::new();
+        write_header(&mut wav, 24000, 2).unwrap();
+        write_samples(&mut wav, &[0.5, -0.25], 2).unwrap();
+
+        assert_eq!(&wav[4..8], &UNKNOWN_LEN.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &UNKNOWN_LEN.to_le_bytes());
+        assert_eq!(wav.len(), 44 + 4 * 4);
+
+        assert_eq!(&wav[20..22], &IEEE_FLOAT.to_le_bytes());
+        assert_eq!(&wav[22..24], &2u16.to_le_bytes());
+        assert_eq!(&wav[24..28], &24000u32.to_le_bytes());
+        assert_eq!(&wav[32..36], &[8, 0, 32, 0]); // block align, bits per sample
+
+        let samples: Vec<f32> = wav[44..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(samples, vec![0.5, 0.5, -0.25, -0.25]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// Errors raised by the synthesis pipeline
+#[derive(Debug)]
+pub enum TTSError {
+    /// The model run did not produce the named output
+    MissingOutput(String),
+    /// No style vectors were given for inference
+    EmptyStyles,
+    /// No token sequence was given for inference
+    EmptyTokens,
+    /// espeak-ng could not phonemize a chunk of text
+    Phonemize {
+        text: String,
+        lan: String,
+        message: String,
+    },
+    /// Phonemization or inference failed
+    Synthesis(Box<dyn std::error::Error>),
+    /// The audio could not be encoded
+    Encoding(String),
+    /// The voices file could not be read
+    Voices(String),
+    /// espeak-ng could not be initialized
+    Espeak(String),
+    /// The model file is missing or could not be loaded
+    Model(String),
+}
+
+impl fmt::Display for TTSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TTSError::MissingOutput(name) => {
+                write!(
+                    f,
+                    "model output '{}' is missing from the inference results",
+                    name
+                )
+            }
+            TTSError::EmptyStyles => write!(f, "no style vectors were provided for inference"),
+            TTSError::EmptyTokens => write!(f, "no tokens were provided for inference"),
+            TTSError::Phonemize { text, lan, message } => {
+                write!(f, "failed to phonemize {:?} as {}: {}", text, lan, message)
+            }
+            TTSError::Synthesis(e) => write!(f, "synthesis failed: {}", e),
+            TTSError::Encoding(message) => write!(f, "failed to encode audio: {}", message),
+            TTSError::Voices(message) => write!(f, "failed to load voices: {}", message),
+            TTSError::Espeak(message) => write!(f, "failed to initialize espeak-ng: {}", message),
+            TTSError::Model(message) => write!(f, "failed to load model: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TTSError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TTSError::Synthesis(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<hound::Error> for TTSError {
+    fn from(e: hound::Error) -> Self {
+        TTSError::Encoding(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for TTSError {
+    fn from(e: std::io::Error) -> Self {
+        TTSError::Encoding(e.to_string())
+    }
+}
@@ -1,3 +1,5 @@
+pub mod audio;
+pub mod error;
 pub mod model;
 pub mod tts;
 pub mod utils;
@@ -1,40 +1,280 @@
 use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
 
 use ndarray::{ArrayBase, IxDyn, OwnedRepr};
 use ort::execution_providers::cpu::CPUExecutionProvider;
-#[cfg(feature = "cuda")]
-use ort::execution_providers::cuda::CUDAExecutionProvider;
+use ort::execution_providers::{
+    CUDAExecutionProvider, CoreMLExecutionProvider, DirectMLExecutionProvider, ExecutionProvider,
+    ExecutionProviderDispatch,
+};
 use ort::logging::LogLevel;
-use ort::session::builder::SessionBuilder;
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
 use ort::{
     session::{Session, SessionInputValue, SessionInputs, SessionOutputs},
     value::{Tensor, Value},
 };
 
+use crate::error::TTSError;
 use crate::utils::debug::format_debug_prefix;
 
 pub struct KokoroModel {
     sess: Session,
+    execution_provider: &'static str,
+}
+
+/// ONNX Runtime session settings for the model
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    /// Use deterministic kernels so identical inputs produce identical audio.
+    /// This mostly affects GPU providers and may cost some performance.
+    pub deterministic: bool,
+    /// Number of threads used within a single operator; `None` lets ONNX Runtime decide.
+    /// Results of some CPU kernels can differ slightly between thread counts.
+    pub intra_threads: Option<usize>,
+    /// How much ONNX Runtime rewrites the graph when loading the model. Higher levels make
+    /// inference faster but loading slower and use more memory while optimizing.
+    pub optimization_level: OptimizationLevel,
+    /// Plan allocations from the shapes of the first run and reuse the plan. This saves
+    /// allocations for repeated inputs of the same size but holds on to more memory, and
+    /// helps little when chunk lengths vary.
+    pub memory_pattern: bool,
+    /// Serve CPU allocations from a growing arena instead of the system allocator. The arena
+    /// is faster but never returns memory to the system, so peak usage stays reserved.
+    pub cpu_arena: bool,
+    /// Fail instead of falling back to the CPU when no GPU execution provider can be
+    /// initialized
+    pub require_gpu: bool,
+    /// Execution providers to try in order, falling back to the next with a warning and to
+    /// the CPU after the last. Empty tries CUDA when built with the `cuda` feature, and
+    /// runs on the CPU otherwise.
+    pub providers: Vec<Provider>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            deterministic: false,
+            intra_threads: None,
+            optimization_level: OptimizationLevel::default(),
+            memory_pattern: true,
+            cpu_arena: true,
+            require_gpu: false,
+            providers: Vec::new(),
+        }
+    }
+}
+
+/// Graph optimization level, see the ONNX Runtime documentation on graph optimizations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    /// No optimizations
+    Disable,
+    /// Semantics-preserving rewrites such as constant folding
+    Basic,
+    /// Basic plus complex node fusions
+    Extended,
+    /// Extended plus layout optimizations, the ONNX Runtime default
+    #[default]
+    All,
+}
+
+impl FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" | "none" => Ok(OptimizationLevel::Disable),
+            "basic" => Ok(OptimizationLevel::Basic),
+            "extended" => Ok(OptimizationLevel::Extended),
+            "all" => Ok(OptimizationLevel::All),
+            _ => Err(format!(
+                "unknown optimization level '{}', expected one of: disable, basic, extended, all",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OptimizationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OptimizationLevel::Disable => "disable",
+            OptimizationLevel::Basic => "basic",
+            OptimizationLevel::Extended => "extended",
+            OptimizationLevel::All => "all",
+        })
+    }
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+/// An ONNX Runtime execution provider the model can run on. Whether it works depends on
+/// the ONNX Runtime library in use, see [`Provider::available`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Cpu,
+    Cuda,
+    CoreMl,
+    DirectMl,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 4] = [
+        Provider::Cpu,
+        Provider::Cuda,
+        Provider::CoreMl,
+        Provider::DirectMl,
+    ];
+
+    /// Providers the ONNX Runtime library was built with that can run on this platform.
+    /// Some may still fail to initialize, e.g. CUDA without a GPU
+    pub fn available() -> Vec<Provider> {
+        Self::ALL
+            .into_iter()
+            .filter(|provider| provider.accelerator().is_none_or(|(usable, _)| usable))
+            .collect()
+    }
+
+    /// Display name, as reported by [`KokoroModel::execution_provider`]
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Cpu => "CPU",
+            Provider::Cuda => "CUDA",
+            Provider::CoreMl => "CoreML",
+            Provider::DirectMl => "DirectML",
+        }
+    }
+
+    /// Name of the CPU fallback used when this provider could not be initialized
+    fn fallback_name(self) -> &'static str {
+        match self {
+            Provider::Cpu => "CPU",
+            Provider::Cuda => "CPU (CUDA unavailable)",
+            Provider::CoreMl => "CPU (CoreML unavailable)",
+            Provider::DirectMl => "CPU (DirectML unavailable)",
+        }
+    }
+
+    /// The ONNX Runtime provider of a GPU or accelerator, with whether ONNX Runtime can use
+    /// it on this platform. `None` for the CPU, which is configured separately
+    fn accelerator(self) -> Option<(bool, ExecutionProviderDispatch)> {
+        fn probe<E: ExecutionProvider + Into<ExecutionProviderDispatch>>(
+            ep: E,
+        ) -> (bool, ExecutionProviderDispatch) {
+            let usable = ep.supported_by_platform() && ep.is_available().unwrap_or(false);
+            (usable, ep.into())
+        }
+        match self {
+            Provider::Cpu => None,
+            Provider::Cuda => Some(probe(CUDAExecutionProvider::default())),
+            Provider::CoreMl => Some(probe(CoreMLExecutionProvider::default())),
+            Provider::DirectMl => Some(probe(DirectMLExecutionProvider::default())),
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Provider::Cpu),
+            "cuda" => Ok(Provider::Cuda),
+            "coreml" => Ok(Provider::CoreMl),
+            "directml" | "dml" => Ok(Provider::DirectMl),
+            _ => Err(format!(
+                "unknown execution provider '{}', expected one of: cpu, cuda, coreml, directml",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Provider::Cpu => "cpu",
+            Provider::Cuda => "cuda",
+            Provider::CoreMl => "coreml",
+            Provider::DirectMl => "directml",
+        })
+    }
 }
 
 impl KokoroModel {
     pub fn new(model_path: String) -> Result<Self, String> {
-        #[cfg(feature = "cuda")]
-        let providers = [CUDAExecutionProvider::default().build()];
+        Self::with_config(model_path, &ModelConfig::default())
+    }
 
-        #[cfg(not(feature = "cuda"))]
-        let providers = [CPUExecutionProvider::default().build()];
+    pub fn with_config(model_path: String, config: &ModelConfig) -> Result<Self, String> {
+        // Register the CPU provider explicitly so the arena setting applies to it
+        let cpu = || {
+            CPUExecutionProvider::default()
+                .with_arena_allocator(config.cpu_arena)
+                .build()
+        };
+
+        let providers = match config.providers.is_empty() {
+            true if cfg!(feature = "cuda") => vec![Provider::Cuda],
+            true => Vec::new(),
+            false => config.providers.clone(),
+        };
 
-        let session = SessionBuilder::new()
-            .map_err(|e| format!("Failed to create session builder: {}", e))?
-            .with_execution_providers(providers)
-            .map_err(|e| format!("Failed to build session: {}", e))?
-            .with_log_level(LogLevel::Warning)
-            .map_err(|e| format!("Failed to set log level: {}", e))?
-            .commit_from_file(model_path)
-            .map_err(|e| format!("Failed to commit from file: {}", e))?;
+        let mut failed = None;
+        for provider in providers {
+            // Requesting the CPU ends the list, since it always works
+            let Some((usable, ep)) = provider.accelerator() else {
+                break;
+            };
+            if !usable {
+                tracing::warn!("{} execution provider is not available", provider.name());
+                failed.get_or_insert(provider);
+                continue;
+            }
 
-        Ok(KokoroModel { sess: session })
+            match build_session(&model_path, config, [ep.error_on_failure(), cpu()]) {
+                Ok(sess) => {
+                    return Ok(KokoroModel {
+                        sess,
+                        execution_provider: provider.name(),
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("{} initialization failed: {}", provider.name(), e);
+                    failed.get_or_insert(provider);
+                }
+            }
+        }
+
+        if config.require_gpu {
+            return Err(match failed {
+                Some(_) => "None of the requested GPU execution providers could be initialized",
+                None => "A GPU is required, but no GPU execution provider was requested",
+            }
+            .to_string());
+        }
+        if failed.is_some() {
+            tracing::warn!("Running on the CPU");
+        }
+        Ok(KokoroModel {
+            sess: build_session(&model_path, config, [cpu()])?,
+            execution_provider: failed.map_or("CPU", Provider::fallback_name),
+        })
+    }
+
+    /// Name of the execution provider the session runs on
+    pub fn execution_provider(&self) -> &'static str {
+        self.execution_provider
     }
 
     pub fn print_info(&self) {
@@ -47,11 +287,10 @@ impl KokoroModel {
             eprintln!("  - {}", output.name);
         }
 
-        #[cfg(feature = "cuda")]
-        eprintln!("Configured with: CUDA execution provider");
-
-        #[cfg(not(feature = "cuda"))]
-        eprintln!("Configured with: CPU execution provider");
+        eprintln!(
+            "Configured with: {} execution provider",
+            self.execution_provider
+        );
     }
 
     pub fn infer(
@@ -63,6 +302,9 @@ impl KokoroModel {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
     ) -> Result<ArrayBase<OwnedRepr<f32>, IxDyn>, Box<dyn std::error::Error>> {
+        check_inputs(&tokens, &styles)?;
+        let _span = tracing::info_span!("infer", tokens = tokens[0].len(), speed).entered();
+
         let shape = [tokens.len(), tokens[0].len()];
         let tokens_flat: Vec<i64> = tokens.into_iter().flatten().collect();
 
@@ -98,9 +340,10 @@ impl KokoroModel {
         ];
 
         let outputs: SessionOutputs = self.sess.run(SessionInputs::from(inputs))?;
-        let (shape, data) = outputs["audio"]
-            .try_extract_tensor::<f32>()
-            .expect("Failed to extract tensor");
+        let (shape, data) = outputs
+            .get("audio")
+            .ok_or_else(|| TTSError::MissingOutput("audio".to_string()))?
+            .try_extract_tensor::<f32>()?;
 
         // Convert Shape and &[f32] to ArrayBase<OwnedRepr<f32>, IxDyn>
         let shape_vec: Vec<usize> = shape.iter().map(|&i| i as usize).collect();
@@ -121,3 +364,121 @@ impl KokoroModel {
         Ok(output_array)
     }
 }
+
+/// A user-supplied ONNX model computing a style vector from reference speech, for voices
+/// cloned from a recording instead of picked from the voices file.
+///
+/// The model must take one `f32` input of shape `(1, samples)` holding mono audio at the
+/// Kokoro sample rate and produce a first output of 256 values, in the layout of one frame
+/// of a voice from the voices file.
+pub struct ReferenceEncoder {
+    sess: Session,
+}
+
+impl ReferenceEncoder {
+    pub fn new(model_path: &str) -> Result<Self, String> {
+        let providers = [CPUExecutionProvider::default().build()];
+        let sess = build_session(model_path, &ModelConfig::default(), providers)?;
+        if sess.inputs.len() != 1 || sess.outputs.is_empty() {
+            return Err(format!(
+                "{}: expected a reference encoder with one input and at least one output",
+                model_path
+            ));
+        }
+        Ok(Self { sess })
+    }
+
+    /// Compute the style vector of `audio`, mono samples at the Kokoro sample rate
+    pub fn encode(&mut self, audio: &[f32]) -> Result<[f32; 256], Box<dyn std::error::Error>> {
+        if audio.is_empty() {
+            return Err("reference audio is empty".into());
+        }
+        let input_name = self.sess.inputs[0].name.clone();
+        let audio = Tensor::from_array(([1, audio.len()], audio.to_vec()))?;
+        let inputs: Vec<(Cow<str>, SessionInputValue)> = vec![(
+            Cow::Owned(input_name),
+            SessionInputValue::Owned(Value::from(audio)),
+        )];
+
+        let outputs: SessionOutputs = self.sess.run(SessionInputs::from(inputs))?;
+        let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+        data.try_into().map_err(|_| {
+            format!(
+                "reference encoder produced shape {:?}, expected 256 values",
+                shape
+            )
+            .into()
+        })
+    }
+}
+
+/// Create a session for the model on the given execution providers
+fn build_session(
+    model_path: &str,
+    config: &ModelConfig,
+    providers: impl AsRef<[ExecutionProviderDispatch]>,
+) -> Result<Session, String> {
+    let session = SessionBuilder::new()
+        .map_err(|e| format!("Failed to create session builder: {}", e))?
+        .with_execution_providers(providers)
+        .map_err(|e| format!("Failed to build session: {}", e))?
+        .with_log_level(LogLevel::Warning)
+        .map_err(|e| format!("Failed to set log level: {}", e))?
+        .with_deterministic_compute(config.deterministic)
+        .map_err(|e| format!("Failed to set deterministic compute: {}", e))?
+        .with_optimization_level(config.optimization_level.into())
+        .map_err(|e| format!("Failed to set optimization level: {}", e))?
+        .with_memory_pattern(config.memory_pattern)
+        .map_err(|e| format!("Failed to set memory pattern: {}", e))?;
+
+    let session = match config.intra_threads {
+        Some(threads) => session
+            .with_intra_threads(threads)
+            .map_err(|e| format!("Failed to set intra-op threads: {}", e))?,
+        None => session,
+    };
+
+    session
+        .commit_from_file(model_path)
+        .map_err(|e| format!("Failed to commit from file: {}", e))
+}
+
+/// Validate inference inputs up front, since building the input tensors indexes their first rows
+fn check_inputs(tokens: &[Vec<i64>], styles: &[Vec<f32>]) -> Result<(), TTSError> {
+    if tokens.first().is_none_or(|t| t.is_empty()) {
+        return Err(TTSError::EmptyTokens);
+    }
+    if styles.first().is_none_or(|s| s.is_empty()) {
+        return Err(TTSError::EmptyStyles);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_inputs() {
+        let tokens = vec![vec![0, 50, 0]];
+        let styles = vec![vec![0.0; 256]];
+        assert!(check_inputs(&tokens, &styles).is_ok());
+
+        assert!(matches!(
+            check_inputs(&tokens, &[]),
+            Err(TTSError::EmptyStyles)
+        ));
+        assert!(matches!(
+            check_inputs(&tokens, &[vec![]]),
+            Err(TTSError::EmptyStyles)
+        ));
+        assert!(matches!(
+            check_inputs(&[], &styles),
+            Err(TTSError::EmptyTokens)
+        ));
+        assert!(matches!(
+            check_inputs(&[vec![]], &styles),
+            Err(TTSError::EmptyTokens)
+        ));
+    }
+}
@@ -0,0 +1,44 @@
+//! Segmentation of text into the chunks that are synthesized one inference at a time.
+//!
+//! [`TTSKoko`](crate::tts::koko::TTSKoko) splits text at sentence boundaries by default.
+//! Set [`InitConfig::text_frontend`](crate::tts::koko::InitConfig::text_frontend) to use
+//! a different strategy, e.g. language-specific normalization or segmentation.
+
+use crate::tts::koko::split_into_chunks;
+use crate::tts::phonemizer::phonemize;
+use crate::tts::tokenize::tokenize;
+
+/// Splits text into chunks to synthesize
+pub trait TextFrontend: Send + Sync {
+    /// Split `text` in language `lan` into chunks, in reading order. Every chunk must
+    /// phonemize to at most 510 tokens, fewer when more than one pad token goes on each side
+    /// (see [`Padding`](crate::tts::koko::Padding)); longer chunks fail to synthesize.
+    ///
+    /// `text` is one stretch of the input: explicit `<break/>` markers and emphasis markup
+    /// have already been handled. Chunks longer than
+    /// [`InitConfig::speech_chunk_words`](crate::tts::koko::InitConfig::speech_chunk_words)
+    /// are split further afterwards.
+    fn process(&self, text: &str, lan: &str) -> Vec<String>;
+}
+
+/// The built-in frontend: chunks of at most `max_tokens` tokens, split at sentence
+/// boundaries, then word boundaries, then inside words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SentenceFrontend {
+    pub max_tokens: usize,
+}
+
+impl Default for SentenceFrontend {
+    /// 500 tokens, leaving a margin below the model's limit
+    fn default() -> Self {
+        Self { max_tokens: 500 }
+    }
+}
+
+impl TextFrontend for SentenceFrontend {
+    fn process(&self, text: &str, _lan: &str) -> Vec<String> {
+        split_into_chunks(text, self.max_tokens, |text| {
+            tokenize(&phonemize(text, "en").unwrap_or_default()).len()
+        })
+    }
+}
@@ -0,0 +1,51 @@
+//! Free-form speaking instructions such as the `instructions` field of OpenAI's speech API.
+//!
+//! Kokoro has no notion of tone or emotion, so only the pace of speech is taken from the
+//! instructions. Everything else in them is ignored.
+
+/// Speed multiplier for a request asking to speak slowly
+const SLOW: f32 = 0.85;
+/// Speed multiplier for a request asking to speak quickly
+const FAST: f32 = 1.15;
+/// Stronger multipliers for "very slowly", "much faster" and the like
+const VERY_SLOW: f32 = 0.7;
+const VERY_FAST: f32 = 1.3;
+
+/// Speed multiplier asked for by `instructions`, e.g. `0.85` for "Speak slowly and calmly".
+/// Instructions that say nothing about pace give `1.0`, and the first mention of pace wins.
+pub fn speed_factor(instructions: &str) -> f32 {
+    let lowercase = instructions.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect();
+    for (i, word) in words.iter().enumerate() {
+        let slow = match *word {
+            "slow" | "slowly" | "slower" | "leisurely" => true,
+            "fast" | "faster" | "quick" | "quickly" | "quicker" | "rapidly" => false,
+            _ => continue,
+        };
+        let very = i > 0 && matches!(words[i - 1], "very" | "really" | "much" | "extremely");
+        return match (slow, very) {
+            (true, false) => SLOW,
+            (true, true) => VERY_SLOW,
+            (false, false) => FAST,
+            (false, true) => VERY_FAST,
+        };
+    }
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_factor() {
+        assert_eq!(speed_factor("Speak slowly and calmly."), SLOW);
+        assert_eq!(speed_factor("Much faster, please!"), VERY_FAST);
+        assert_eq!(speed_factor("QUICK delivery, then slow down"), FAST);
+        assert_eq!(speed_factor("Sound cheerful and emphatic"), 1.0);
+        assert_eq!(speed_factor(""), 1.0);
+    }
+}
@@ -1,21 +1,98 @@
-use crate::model::KokoroModel;
+use crate::audio::AudioFormat;
+use crate::audio::g711::{self, Law};
+use crate::audio::level::Levels;
+use crate::audio::ogg_flac;
+use crate::audio::quantize::Quantization;
+use crate::audio::resample::resample;
+use crate::audio::wav_f16;
+use crate::audio::wav_stream;
+use crate::error::TTSError;
+use crate::model::{KokoroModel, ModelConfig};
+use crate::tts::frontend::TextFrontend;
+use crate::tts::language::detect_language;
+use crate::tts::phonemizer::{phonemize, phonemize_with_separator};
+use crate::tts::preprocess::{Segment, speak_punctuation, split_emphasis};
 use crate::tts::tokenize::tokenize;
+use crate::tts::voice_info::VoiceInfo;
+use crate::tts::voice_profile::VoiceProfiles;
 use crate::utils::debug::format_debug_prefix;
-use lazy_static::lazy_static;
+use arc_swap::ArcSwap;
+use espeak_rs::ESpeakResult;
 use ndarray::Array3;
 use ndarray_npy::NpzReader;
+use rayon::prelude::*;
+use safetensors::{Dtype, SafeTensors};
+use serde::Serialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::fs::File;
-use std::path::Path;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use espeak_rs::text_to_phonemes;
+/// Length of speech per phoneme token at speed 1.0, about 12 tokens a second. This is a
+/// rough estimate for English prose, not a measured average, so treat durations derived
+/// from it as ballpark figures
+const SECONDS_PER_TOKEN: f32 = 0.08;
 
-// Global mutex to serialize espeak-rs calls to prevent phoneme randomization
-// espeak-rs uses global state internally and is not thread-safe
-lazy_static! {
-    static ref ESPEAK_MUTEX: Mutex<()> = Mutex::new(());
+/// Range `speed` is clamped to before inference. Outside it the model produces noise or
+/// nothing at all
+pub const MIN_SPEED: f32 = 0.1;
+pub const MAX_SPEED: f32 = 5.0;
+
+/// Most tokens of a chunk, not counting padding, that can be synthesized. The last row of
+/// the 511-row style tensors, which are indexed by token count
+const MODEL_MAX_TOKENS: usize = 510;
+
+/// Most tokens the model accepts in one inference, padding included
+const MODEL_MAX_INPUT: usize = 512;
+
+/// Longest chunk, in words, synthesized with a single blend when morphing between voices
+const MORPH_CHUNK_WORDS: usize = 6;
+
+/// Environment variable read by espeak-rs to locate the directory containing `espeak-ng-data`
+const ESPEAK_DATA_ENV: &str = "PIPER_ESPEAKNG_DATA_DIRECTORY";
+const ESPEAK_DATA_DIR_NAME: &str = "espeak-ng-data";
+
+/// `koko` data directories following the XDG Base Directory specification: the user's
+/// `$XDG_DATA_HOME` (default `~/.local/share`) first, then each of `$XDG_DATA_DIRS`
+/// (default `/usr/local/share:/usr/share`). Relative entries are ignored, as the spec requires.
+#[cfg_attr(windows, allow(dead_code))]
+fn xdg_data_dirs(
+    xdg_data_home: Option<&str>,
+    home: Option<&str>,
+    xdg_data_dirs: Option<&str>,
+) -> Vec<PathBuf> {
+    let is_usable = |dir: &&str| !dir.is_empty() && Path::new(dir).is_absolute();
+
+    let data_home = match xdg_data_home.filter(is_usable) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(home.unwrap_or(".")).join(".local/share"),
+    };
+
+    let data_dirs: Vec<PathBuf> = match xdg_data_dirs.map(|dirs| {
+        dirs.split(':')
+            .filter(is_usable)
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    }) {
+        Some(dirs) if !dirs.is_empty() => dirs,
+        _ => vec![
+            PathBuf::from("/usr/local/share"),
+            PathBuf::from("/usr/share"),
+        ],
+    };
+
+    let mut search_dirs: Vec<PathBuf> = Vec::new();
+    for dir in std::iter::once(data_home).chain(data_dirs) {
+        let dir = dir.join("koko");
+        if !search_dirs.contains(&dir) {
+            search_dirs.push(dir);
+        }
+    }
+    search_dirs
 }
 
 #[derive(Debug, Clone)]
@@ -24,9 +101,15 @@ pub struct TTSOpts<'a> {
     pub lan: &'a str,
     pub style_name: &'a str,
     pub save_path: &'a str,
-    pub mono: bool,
-    pub speed: f32,
+    /// Number of output channels, each carrying the same signal
+    pub channels: u16,
+    /// Rate of speech as a multiplier, see [`TTSRawAudioOpts::speed`]
+    pub speed: Option<f32>,
+    /// Silence tokens inserted before every chunk. The pause they produce scales with speed
     pub initial_silence: Option<usize>,
+    /// Silence added before the audio, in milliseconds
+    pub initial_silence_ms: Option<u32>,
+    pub format: AudioFormat,
 }
 
 #[derive(Debug, Clone)]
@@ -34,19 +117,83 @@ pub struct TTSRawAudioOpts<'a> {
     pub txt: &'a str,
     pub lan: &'a str,
     pub style_name: &'a str,
-    pub speed: f32,
+    /// Rate of speech as a multiplier: 2.0 takes half as long as 1.0 and 0.5 twice as long.
+    /// The model divides the predicted duration of every phoneme by it, so higher is always
+    /// faster. Clamped to [`MIN_SPEED`]..=[`MAX_SPEED`]. `None` uses the voice's profile
+    /// speed, see [`TTSKoko::voice_speed`]
+    pub speed: Option<f32>,
+    /// Silence tokens inserted before every chunk. The pause they produce scales with speed
     pub initial_silence: Option<usize>,
+    /// Silence added before the audio, in milliseconds
+    pub initial_silence_ms: Option<u32>,
     pub request_id: Option<&'a str>,
     pub instance_id: Option<&'a str>,
     pub chunk_number: Option<usize>,
 }
 
+/// What was synthesized for each chunk of a request, see
+/// [`TTSKoko::tts_raw_audio_with_report`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SynthesisReport {
+    pub chunks: Vec<ChunkReport>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChunkReport {
+    /// Length of the chunk's text in characters
+    pub text_len: usize,
+    /// Phoneme tokens fed to the model, without padding
+    pub token_count: usize,
+    /// Samples of audio the chunk produced
+    pub sample_count: usize,
+    /// Time spent waiting for and running the model, in milliseconds
+    pub inference_ms: f64,
+}
+
+/// A synthesis failure that still carries the audio produced before it
+#[derive(Debug)]
+pub struct PartialAudioError {
+    /// Audio of all chunks that completed before the failing one
+    pub audio: Vec<f32>,
+    /// Zero-based index of the chunk that failed
+    pub chunk_index: usize,
+    /// Text of the chunk that failed
+    pub chunk_text: String,
+    pub source: Box<dyn std::error::Error>,
+}
+
+impl fmt::Display for PartialAudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {} ({:?}) failed: {}",
+            self.chunk_index, self.chunk_text, self.source
+        )
+    }
+}
+
+impl std::error::Error for PartialAudioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Style vectors of every voice, indexed by token count
+type Styles = HashMap<String, Vec<[[f32; 256]; 1]>>;
+
 #[derive(Clone)]
 pub struct TTSKoko {
     #[allow(dead_code)]
     model_path: String,
+    /// Shared between clones and locked for one chunk's inference at a time, never for a
+    /// whole request, so concurrent requests on the same instance take turns chunk by chunk
     model: Arc<Mutex<KokoroModel>>,
-    styles: HashMap<String, Vec<[[f32; 256]; 1]>>,
+    /// Shared between clones and swapped atomically by `reload_voices`
+    styles: Arc<ArcSwap<Styles>>,
+    /// Where the model was loaded from after searching the standard locations
+    model_resolved_path: String,
+    /// Source of the current voices, updated together with `styles`
+    voices_source: Arc<ArcSwap<String>>,
     init_config: InitConfig,
 }
 
@@ -55,6 +202,102 @@ pub struct InitConfig {
     pub model_url: String,
     pub voices_url: String,
     pub sample_rate: u32,
+    /// Directory holding the espeak-ng data (either `espeak-ng-data` itself or its parent)
+    pub espeak_data_path: Option<String>,
+    /// Detect the language of each chunk and phonemize it accordingly,
+    /// falling back to the requested language when detection is uncertain
+    pub detect_language: bool,
+    /// ONNX Runtime session settings
+    pub model_config: ModelConfig,
+    /// Per-voice speed and pitch adjustments
+    pub voice_profiles: VoiceProfiles,
+    /// Tokens added around every chunk before inference
+    pub padding: Padding,
+    /// Render `*word*` and `[[emphasis]]...[[/emphasis]]` markup as emphasis. When `None`
+    /// the markup is synthesized as ordinary text
+    pub emphasis: Option<Emphasis>,
+    /// Split text into chunks of at most this many words when streaming, on top of the
+    /// token limit every chunk is held to. Smaller chunks start playing sooner, but each
+    /// chunk is synthesized on its own, so intonation across chunk boundaries suffers
+    pub speech_chunk_words: Option<usize>,
+    /// Speak the last this many words of the previous chunk before each chunk, then cut their
+    /// audio off again, so chunks continue the intonation of the one before instead of
+    /// starting afresh. Each chunk then costs an extra, short inference
+    pub chunk_context_words: Option<usize>,
+    /// Only load these voices from the voices file, to save memory and startup time.
+    /// Requests for other voices then fail
+    pub voices_filter: Option<Vec<String>>,
+    /// Speak punctuation aloud ("comma", "period") in the requested language
+    pub verbatim: bool,
+    /// Divide blended styles such as `af_sarah.6+af_nicole.6` by the sum of their portions,
+    /// so the portions only set the ratio between the voices. By default the styles are
+    /// summed as weighted, and portions adding up to more than 1 make louder speech that
+    /// may clip
+    pub normalize_blends: bool,
+    /// Fail requests for blends naming a voice that is not loaded. By default the missing
+    /// voice is left out of the blend with a warning
+    pub strict_blends: bool,
+    /// Rewrites espeak's phonemes before tokenization, e.g. with [`PhonemeRules::apply`], to
+    /// correct recurring mispronunciations
+    ///
+    /// [`PhonemeRules::apply`]: crate::tts::phoneme_rules::PhonemeRules::apply
+    pub phoneme_transform: Option<PhonemeTransform>,
+    /// Splits text into chunks instead of the built-in sentence splitter
+    pub text_frontend: Option<Arc<dyn TextFrontend>>,
+    /// Leave out chunks that fail to synthesize, logging a warning with their text, instead
+    /// of failing the whole request
+    pub skip_failed_chunks: bool,
+    /// How samples are converted for the 16-bit formats (G.711 and Ogg FLAC). WAV output
+    /// keeps float samples and is not affected
+    pub quantization: Quantization,
+    /// Character espeak-ng places between phonemes, see [`phonemize_with_separator`]. The
+    /// bundled vocab has no token for a separator, so this needs a model and vocab that
+    /// were trained on separated phonemes
+    pub phoneme_separator: Option<char>,
+}
+
+/// Function rewriting a phoneme string, see [`InitConfig::phoneme_transform`]
+pub type PhonemeTransform = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// How emphasized spans are rendered relative to the surrounding speech
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emphasis {
+    /// Speed multiplier, below 1 for slower speech
+    pub speed: f32,
+    /// Amplitude multiplier
+    pub gain: f32,
+}
+
+impl Default for Emphasis {
+    fn default() -> Self {
+        Self {
+            speed: 0.85,
+            gain: 1.4,
+        }
+    }
+}
+
+/// Padding placed at both ends of each chunk's token sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Padding {
+    /// Token ID used as padding
+    pub token: i64,
+    /// Number of pad tokens on each side, 0 disables padding
+    pub count: usize,
+}
+
+impl Default for Padding {
+    /// A single `0` token on each side, as the Kokoro v1.0 model expects
+    fn default() -> Self {
+        Self { token: 0, count: 1 }
+    }
+}
+
+impl Padding {
+    /// Most tokens a chunk can have once this padding is added around it
+    fn max_tokens(&self) -> usize {
+        MODEL_MAX_TOKENS.min(MODEL_MAX_INPUT.saturating_sub(2 * self.count))
+    }
 }
 
 impl Default for InitConfig {
@@ -63,6 +306,23 @@ impl Default for InitConfig {
             model_url: "https://github.com/thewh1teagle/kokoro-onnx/releases/download/model-files-v1.0/kokoro-v1.0.onnx".into(),
             voices_url: "https://github.com/thewh1teagle/kokoro-onnx/releases/download/model-files-v1.0/voices-v1.0.bin".into(),
             sample_rate: 24000,
+            espeak_data_path: None,
+            detect_language: false,
+            model_config: ModelConfig::default(),
+            voice_profiles: VoiceProfiles::default(),
+            padding: Padding::default(),
+            emphasis: None,
+            speech_chunk_words: None,
+            chunk_context_words: None,
+            voices_filter: None,
+            phoneme_transform: None,
+            text_frontend: None,
+            skip_failed_chunks: false,
+            quantization: Quantization::default(),
+            phoneme_separator: None,
+            verbatim: false,
+            normalize_blends: false,
+            strict_blends: false,
         }
     }
 }
@@ -72,6 +332,27 @@ impl TTSKoko {
         Self::from_config(model_path, voices_path, InitConfig::default())
     }
 
+    /// Data directories searched for the model and voices files, in order of preference
+    #[cfg(windows)]
+    fn standard_data_dirs() -> Vec<PathBuf> {
+        // %LOCALAPPDATA%\koko, then the roaming %APPDATA%\koko
+        [dirs::data_local_dir(), dirs::data_dir()]
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join("koko"))
+            .collect()
+    }
+
+    /// Data directories searched for the model and voices files, in order of preference
+    #[cfg(not(windows))]
+    fn standard_data_dirs() -> Vec<PathBuf> {
+        xdg_data_dirs(
+            env::var("XDG_DATA_HOME").ok().as_deref(),
+            env::var("HOME").ok().as_deref(),
+            env::var("XDG_DATA_DIRS").ok().as_deref(),
+        )
+    }
+
     /// Find file in standard locations
     fn find_file_in_standard_locations(file_path: &str, file_type: &str) -> String {
         // If the provided path exists, use it as-is
@@ -87,32 +368,12 @@ impl TTSKoko {
 
         // Define standard search paths in order of preference
         let search_paths = match file_type {
-            "model" => vec![
-                // User-specific data directory
-                format!(
-                    "{}/.local/share/koko/{}",
-                    env::var("HOME").unwrap_or_else(|_| ".".to_string()),
-                    file_name
-                ),
-                // System-wide data directories
-                format!("/usr/local/share/koko/{}", file_name),
-                format!("/usr/share/koko/{}", file_name),
+            "model" | "voices" => Self::standard_data_dirs()
+                .into_iter()
+                .map(|dir| dir.join(file_name).to_string_lossy().into_owned())
                 // Current behavior as fallback
-                file_path.to_string(),
-            ],
-            "voices" => vec![
-                // User-specific data directory
-                format!(
-                    "{}/.local/share/koko/{}",
-                    env::var("HOME").unwrap_or_else(|_| ".".to_string()),
-                    file_name
-                ),
-                // System-wide data directories
-                format!("/usr/local/share/koko/{}", file_name),
-                format!("/usr/share/koko/{}", file_name),
-                // Current behavior as fallback
-                file_path.to_string(),
-            ],
+                .chain(std::iter::once(file_path.to_string()))
+                .collect(),
             _ => vec![file_path.to_string()],
         };
 
@@ -143,156 +404,321 @@ impl TTSKoko {
         Self::find_file_in_standard_locations(model_path, "model")
     }
 
+    /// Point espeak-rs at an explicit data directory and make sure it initializes
+    pub fn init_espeak(espeak_data_path: Option<&str>) -> Result<(), String> {
+        if let Some(path) = espeak_data_path {
+            let path = Path::new(path);
+            if !path.exists() {
+                return Err(format!(
+                    "espeak-ng data path does not exist: {}",
+                    path.display()
+                ));
+            }
+
+            // espeak-rs expects the directory that contains `espeak-ng-data`
+            let data_dir: PathBuf = if path.join(ESPEAK_DATA_DIR_NAME).exists() {
+                path.to_path_buf()
+            } else if path
+                .file_name()
+                .is_some_and(|name| name == ESPEAK_DATA_DIR_NAME)
+            {
+                path.parent().unwrap_or(Path::new(".")).to_path_buf()
+            } else {
+                return Err(format!(
+                    "No {} directory found in {}",
+                    ESPEAK_DATA_DIR_NAME,
+                    path.display()
+                ));
+            };
+
+            tracing::info!("Using espeak-ng data from: {}", data_dir.display());
+            // SAFETY: set once during initialization, before the first phonemization reads it
+            unsafe {
+                env::set_var(ESPEAK_DATA_ENV, &data_dir);
+            }
+        }
+
+        // espeak-rs initializes lazily on first use, so run a tiny phonemization now
+        // to surface a broken setup at startup rather than on the first synthesis
+        phonemize("a", "en").map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    /// Load the model and voices, exiting the process with a message on failure. Use
+    /// [`TTSKoko::try_from_config`] where that is not acceptable
     pub fn from_config(model_path: &str, voices_path: &str, cfg: InitConfig) -> Self {
+        Self::try_from_config(model_path, voices_path, cfg).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            if let TTSError::Espeak(_) = e {
+                eprintln!(
+                    "Pass the espeak-ng data directory with --espeak-data or the KOKO_ESPEAK_DATA environment variable"
+                );
+            }
+            std::process::exit(1);
+        })
+    }
+
+    /// Load the model and voices, returning an error when espeak-ng, the model or the
+    /// voices file cannot be set up
+    pub fn try_from_config(
+        model_path: &str,
+        voices_path: &str,
+        cfg: InitConfig,
+    ) -> Result<Self, TTSError> {
+        Self::init_espeak(cfg.espeak_data_path.as_deref()).map_err(TTSError::Espeak)?;
+        if let Some(separator) = cfg.phoneme_separator {
+            tracing::warn!(
+                "Separating phonemes with {:?}, which the model's vocab must have been built with",
+                separator
+            );
+        }
+
         // Find model file in standard locations
         let resolved_model_path = Self::find_model_file(model_path);
-
         if !Path::new(&resolved_model_path).exists() {
-            eprintln!("Model file not found: {}", resolved_model_path);
-            eprintln!("Please download the model file from: {}", cfg.model_url);
-            eprintln!("And place it at one of these locations:");
-            eprintln!("  - {}", resolved_model_path);
-            eprintln!("  - ~/.local/share/koko/kokoro-v1.0.onnx");
-            eprintln!("  - /usr/local/share/koko/kokoro-v1.0.onnx");
-            eprintln!("  - /usr/share/koko/kokoro-v1.0.onnx");
-            std::process::exit(1);
+            return Err(TTSError::Model(not_found_message(
+                &resolved_model_path,
+                "kokoro-v1.0.onnx",
+                &cfg.model_url,
+            )));
         }
 
         // Find voices file in standard locations
         let resolved_voices_path = Self::find_voices_file(voices_path);
-
         if !Path::new(&resolved_voices_path).exists() {
-            eprintln!("Voices data file not found: {}", resolved_voices_path);
-            eprintln!(
-                "Please download the voices data file from: {}",
-                cfg.voices_url
-            );
-            eprintln!("And place it at one of these locations:");
-            eprintln!("  - {}", resolved_voices_path);
-            eprintln!("  - ~/.local/share/koko/voices-v1.0.bin");
-            eprintln!("  - /usr/local/share/koko/voices-v1.0.bin");
-            eprintln!("  - /usr/share/koko/voices-v1.0.bin");
-            std::process::exit(1);
+            return Err(TTSError::Voices(not_found_message(
+                &resolved_voices_path,
+                "voices-v1.0.bin",
+                &cfg.voices_url,
+            )));
         }
 
         let model = Arc::new(Mutex::new(
-            KokoroModel::new(resolved_model_path.to_string())
-                .expect("Failed to create Kokoro TTS model"),
+            KokoroModel::with_config(resolved_model_path.to_string(), &cfg.model_config)
+                .map_err(TTSError::Model)?,
         ));
         // model.lock().unwrap().print_info();
 
-        let styles = Self::load_voices(&resolved_voices_path);
+        let styles = Self::load_voices(&resolved_voices_path, cfg.voices_filter.as_deref())?;
 
-        TTSKoko {
+        Ok(TTSKoko {
             model_path: model_path.to_string(),
             model,
-            styles,
+            styles: Arc::new(ArcSwap::from_pointee(styles)),
+            model_resolved_path: absolute_path(&resolved_model_path),
+            voices_source: Arc::new(ArcSwap::from_pointee(absolute_path(&resolved_voices_path))),
             init_config: cfg,
-        }
+        })
     }
 
-    fn split_text_into_chunks(&self, text: &str, max_tokens: usize) -> Vec<String> {
-        let mut chunks = Vec::new();
+    /// Split text into chunks to synthesize, each flagged with whether it is emphasized.
+    /// Chunks always end at the explicit breaks found by [`split_breaks`], and emphasized
+    /// spans always get chunks of their own. With `max_words`, chunks are split
+    /// further with [`split_text_into_speech_chunks`]. Morphing voices are held to
+    /// [`MORPH_CHUNK_WORDS`] so the blend changes often enough to be heard as a glide
+    fn text_chunks(
+        &self,
+        text: &str,
+        lan: &str,
+        style_name: &str,
+        max_words: Option<usize>,
+    ) -> Vec<(String, bool)> {
+        let max_words = match style_name.contains('>') {
+            true => Some(
+                max_words
+                    .unwrap_or(MORPH_CHUNK_WORDS)
+                    .min(MORPH_CHUNK_WORDS),
+            ),
+            false => max_words,
+        };
+        split_breaks(text)
+            .into_iter()
+            .flat_map(|section| self.section_chunks(section, lan, max_words))
+            .collect()
+    }
 
-        // First split by sentences - using common sentence ending punctuation
-        let sentences: Vec<&str> = text
-            .split(['.', '?', '!', ';'])
-            .filter(|s| !s.trim().is_empty())
-            .collect();
+    /// The chunks of a section of text between explicit breaks, see [`TTSKoko::text_chunks`]
+    fn section_chunks(
+        &self,
+        text: &str,
+        lan: &str,
+        max_words: Option<usize>,
+    ) -> Vec<(String, bool)> {
+        let text = if self.init_config.verbatim {
+            Cow::Owned(speak_punctuation(text, lan))
+        } else {
+            Cow::Borrowed(text)
+        };
+        let segments = match self.init_config.emphasis {
+            Some(_) => split_emphasis(&text),
+            None => vec![Segment {
+                text: text.to_string(),
+                emphasized: false,
+            }],
+        };
 
-        let mut current_chunk = String::new();
+        let chunks = match &self.init_config.text_frontend {
+            Some(frontend) => segments
+                .iter()
+                .flat_map(|segment| {
+                    let chunks = frontend.process(&segment.text, lan);
+                    chunks.into_iter().map(|chunk| (chunk, segment.emphasized))
+                })
+                .collect(),
+            // Using 500 to leave 12 tokens of margin
+            None => split_segments(&segments, 500, |text| {
+                tokenize(&self.phonemes(text, "en").unwrap_or_default()).len()
+            }),
+        };
+        chunks
+            .into_iter()
+            .flat_map(|(chunk, emphasized)| match max_words {
+                Some(max_words) => split_text_into_speech_chunks(&chunk, max_words)
+                    .into_iter()
+                    .map(|chunk| (chunk, emphasized))
+                    .collect(),
+                None => vec![(chunk, emphasized)],
+            })
+            .collect()
+    }
 
-        for sentence in sentences {
-            // Clean up the sentence and add back punctuation
-            let sentence = format!("{}.", sentence.trim());
+    /// Synthesize a chunk from [`TTSKoko::text_chunks`], applying emphasis if it is flagged.
+    /// `position` is where the chunk lies in the whole text, see [`chunk_positions`], and
+    /// `previous` is the chunk before it
+    fn synthesize_text_chunk(
+        &self,
+        (chunk, emphasized): &(String, bool),
+        previous: Option<&(String, bool)>,
+        position: f32,
+        opts: &TTSRawAudioOpts,
+        styles: &Styles,
+    ) -> Result<(Vec<f32>, ChunkReport), Box<dyn std::error::Error>> {
+        let context = self.chunk_context(previous);
+        let emphasis = match self.init_config.emphasis {
+            Some(emphasis) if *emphasized => emphasis,
+            _ => {
+                return self.synthesize_in_context(
+                    chunk,
+                    context.as_deref(),
+                    position,
+                    opts,
+                    styles,
+                );
+            }
+        };
 
-            // Convert to phonemes to check token count
-            let sentence_phonemes = {
-                let _guard = ESPEAK_MUTEX.lock().unwrap();
-                text_to_phonemes(&sentence, "en", None, true, false)
-                    .unwrap_or_default()
-                    .join("")
-            };
-            let token_count = tokenize(&sentence_phonemes).len();
-
-            if token_count > max_tokens {
-                // If single sentence is too long, split by words
-                let words: Vec<&str> = sentence.split_whitespace().collect();
-                let mut word_chunk = String::new();
-
-                for word in words {
-                    let test_chunk = if word_chunk.is_empty() {
-                        word.to_string()
-                    } else {
-                        format!("{} {}", word_chunk, word)
-                    };
-
-                    let test_phonemes = {
-                        let _guard = ESPEAK_MUTEX.lock().unwrap();
-                        text_to_phonemes(&test_chunk, "en", None, true, false)
-                            .unwrap_or_default()
-                            .join("")
-                    };
-                    let test_tokens = tokenize(&test_phonemes).len();
-
-                    if test_tokens > max_tokens {
-                        if !word_chunk.is_empty() {
-                            chunks.push(word_chunk);
-                        }
-                        word_chunk = word.to_string();
-                    } else {
-                        word_chunk = test_chunk;
-                    }
-                }
+        let opts = TTSRawAudioOpts {
+            speed: Some(self.request_speed(opts) * emphasis.speed),
+            ..opts.clone()
+        };
+        let (mut audio, report) =
+            self.synthesize_in_context(chunk, context.as_deref(), position, &opts, styles)?;
+        apply_gain(&mut audio, emphasis.gain, self.init_config.sample_rate);
+        Ok((audio, report))
+    }
 
-                if !word_chunk.is_empty() {
-                    chunks.push(word_chunk);
-                }
-            } else if !current_chunk.is_empty() {
-                // Try to append to current chunk
-                let test_text = format!("{} {}", current_chunk, sentence);
-                let test_phonemes = {
-                    let _guard = ESPEAK_MUTEX.lock().unwrap();
-                    text_to_phonemes(&test_text, "en", None, true, false)
-                        .unwrap_or_default()
-                        .join("")
-                };
-                let test_tokens = tokenize(&test_phonemes).len();
+    /// The words of `previous` to speak as context before the next chunk, if enabled
+    fn chunk_context(&self, previous: Option<&(String, bool)>) -> Option<String> {
+        let words = self
+            .init_config
+            .chunk_context_words
+            .filter(|&words| words > 0)?;
+        let previous: Vec<&str> = previous?.0.split_whitespace().collect();
+        let context = previous[previous.len().saturating_sub(words)..].join(" ");
+        (!context.is_empty()).then_some(context)
+    }
 
-                if test_tokens > max_tokens {
-                    // If combining would exceed limit, start new chunk
-                    chunks.push(current_chunk);
-                    current_chunk = sentence;
-                } else {
-                    current_chunk = test_text;
-                }
-            } else {
-                current_chunk = sentence;
-            }
+    /// Synthesize `chunk` preceded by `context`, the end of the previous chunk, then cut the
+    /// context off again. The cut is placed at the quietest point near where the context
+    /// alone would end, which is usually the pause between the two sentences
+    fn synthesize_in_context(
+        &self,
+        chunk: &str,
+        context: Option<&str>,
+        position: f32,
+        opts: &TTSRawAudioOpts,
+        styles: &Styles,
+    ) -> Result<(Vec<f32>, ChunkReport), Box<dyn std::error::Error>> {
+        let Some(context) = context else {
+            return self.synthesize_chunk(chunk, position, opts, styles);
+        };
+        let combined = format!("{} {}", context, chunk);
+        if tokenize(&self.phonemes(&combined, opts.lan).unwrap_or_default()).len()
+            > self.init_config.padding.max_tokens()
+        {
+            return self.synthesize_chunk(chunk, position, opts, styles);
         }
 
-        // Add the last chunk if not empty
-        if !current_chunk.is_empty() {
-            chunks.push(current_chunk);
-        }
+        let (context_audio, context_report) =
+            self.synthesize_chunk(context, position, opts, styles)?;
+        let context_len = context_audio.len();
+        let (mut audio, report) = self.synthesize_chunk(&combined, position, opts, styles)?;
+        let sample_rate = self.init_config.sample_rate as usize;
+        let margin = context_len / 4;
+        let cut = quietest_point(
+            &audio,
+            context_len.saturating_sub(margin),
+            context_len + margin,
+            sample_rate / 100,
+        );
+        audio.drain(..cut);
 
-        chunks
+        // Fade in over 5 ms so the cut does not click
+        let fade = (sample_rate / 200).min(audio.len());
+        for (i, sample) in audio[..fade].iter_mut().enumerate() {
+            *sample *= i as f32 / fade as f32;
+        }
+        let report = ChunkReport {
+            text_len: chunk.chars().count(),
+            sample_count: audio.len(),
+            inference_ms: report.inference_ms + context_report.inference_ms,
+            ..report
+        };
+        Ok((audio, report))
     }
 
     pub fn tts_raw_audio_opts(
         &self,
         opts: TTSRawAudioOpts,
     ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-        self.tts_raw_audio(
-            opts.txt,
-            opts.lan,
-            opts.style_name,
-            opts.speed,
-            opts.initial_silence,
-            opts.request_id,
-            opts.instance_id,
-            opts.chunk_number,
-        )
+        self.tts_raw_audio_partial(opts).map_err(|e| e.into())
+    }
+
+    /// Like [`TTSKoko::tts_raw_audio_opts`], but runs synthesis on Tokio's blocking thread
+    /// pool, so async callers keep serving other tasks while the model is busy
+    #[cfg(feature = "async")]
+    pub async fn synthesize(&self, opts: TTSRawAudioOpts<'_>) -> Result<Vec<f32>, TTSError> {
+        let tts = self.clone();
+        let txt = opts.txt.to_string();
+        let lan = opts.lan.to_string();
+        let style_name = opts.style_name.to_string();
+        let request_id = opts.request_id.map(str::to_string);
+        let instance_id = opts.instance_id.map(str::to_string);
+        let TTSRawAudioOpts {
+            speed,
+            initial_silence,
+            initial_silence_ms,
+            chunk_number,
+            ..
+        } = opts;
+
+        tokio::task::spawn_blocking(move || {
+            tts.tts_raw_audio_opts(TTSRawAudioOpts {
+                txt: &txt,
+                lan: &lan,
+                style_name: &style_name,
+                speed,
+                initial_silence,
+                initial_silence_ms,
+                request_id: request_id.as_deref(),
+                instance_id: instance_id.as_deref(),
+                chunk_number,
+            })
+            // Errors are not Send, so only their message leaves the blocking thread
+            .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| TTSError::Synthesis(e.into()))?
+        .map_err(|e| TTSError::Synthesis(e.into()))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -307,71 +733,299 @@ impl TTSKoko {
         instance_id: Option<&str>,
         chunk_number: Option<usize>,
     ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        self.tts_raw_audio_opts(TTSRawAudioOpts {
+            txt,
+            lan,
+            style_name,
+            speed: Some(speed),
+            initial_silence,
+            initial_silence_ms: None,
+            request_id,
+            instance_id,
+            chunk_number,
+        })
+    }
+
+    /// Synthesize audio, keeping the audio of every chunk that completed before a failure.
+    ///
+    /// On error the returned [`PartialAudioError`] carries the successfully synthesized
+    /// prefix, so callers can still save it for long documents.
+    pub fn tts_raw_audio_partial(
+        &self,
+        opts: TTSRawAudioOpts,
+    ) -> Result<Vec<f32>, PartialAudioError> {
+        self.tts_raw_audio_report_partial(opts)
+            .map(|(audio, _)| audio)
+    }
+
+    /// Like [`TTSKoko::tts_raw_audio_opts`], also returning what was synthesized for each
+    /// chunk, e.g. for monitoring
+    pub fn tts_raw_audio_with_report(
+        &self,
+        opts: TTSRawAudioOpts,
+    ) -> Result<(Vec<f32>, SynthesisReport), Box<dyn std::error::Error>> {
+        self.tts_raw_audio_report_partial(opts)
+            .map_err(|e| e.into())
+    }
+
+    fn tts_raw_audio_report_partial(
+        &self,
+        opts: TTSRawAudioOpts,
+    ) -> Result<(Vec<f32>, SynthesisReport), PartialAudioError> {
+        let opts = TTSRawAudioOpts {
+            speed: opts.speed.map(clamp_speed),
+            ..opts
+        };
+        let _span = synthesis_span(&opts).entered();
+        // Keep the voices this request started with, even if they are reloaded meanwhile
+        let styles = self.styles.load_full();
         // Split text into appropriate chunks
-        let chunks = self.split_text_into_chunks(txt, 500); // Using 500 to leave 12 tokens of margin
-        let mut final_audio = Vec::new();
-
-        for chunk in chunks {
-            // Convert chunk to phonemes
-            let phonemes = {
-                let _guard = ESPEAK_MUTEX.lock().unwrap();
-                text_to_phonemes(&chunk, lan, None, true, false)
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?
-                    .join("")
-            };
-            let debug_prefix = format_debug_prefix(request_id, instance_id);
-            let chunk_info = chunk_number
-                .map(|n| format!("Chunk: {}, ", n))
-                .unwrap_or_default();
-            tracing::debug!(
-                "{} {}text: '{}' -> phonemes: '{}'",
-                debug_prefix,
-                chunk_info,
-                chunk,
-                phonemes
-            );
-            let mut tokens = tokenize(&phonemes);
+        let chunks = self.text_chunks(opts.txt, opts.lan, opts.style_name, None);
+        let positions = chunk_positions(&chunks);
+        let mut final_audio = self.silence(opts.initial_silence_ms);
+        let mut report = SynthesisReport::default();
 
-            for _ in 0..initial_silence.unwrap_or(0) {
-                tokens.insert(0, 30);
+        for (chunk_index, (chunk, position)) in chunks.iter().zip(positions).enumerate() {
+            let previous = chunk_index.checked_sub(1).map(|i| &chunks[i]);
+            match self.synthesize_text_chunk(chunk, previous, position, &opts, &styles) {
+                Ok((chunk_audio, chunk_report)) => {
+                    final_audio.extend_from_slice(&chunk_audio);
+                    report.chunks.push(chunk_report);
+                }
+                Err(source) if self.init_config.skip_failed_chunks => {
+                    skip_chunk(chunk_index, &chunk.0, source);
+                }
+                Err(source) => {
+                    return Err(PartialAudioError {
+                        audio: final_audio,
+                        chunk_index,
+                        chunk_text: chunk.0.clone(),
+                        source,
+                    });
+                }
             }
+        }
 
-            // Get style vectors once
-            let styles = self.mix_styles(style_name, tokens.len())?;
+        Ok((final_audio, report))
+    }
 
-            // pad a 0 to start and end of tokens
-            let mut padded_tokens = vec![0];
-            for &token in &tokens {
-                padded_tokens.push(token);
-            }
-            padded_tokens.push(0);
+    /// Synthesize audio chunk by chunk, handing each chunk's samples to `on_chunk` as soon as
+    /// it is ready. This lets callers start playback or writing before the whole text is done.
+    /// Stops at the first error from synthesis or from `on_chunk`.
+    pub fn tts_raw_audio_streaming<F>(
+        &self,
+        opts: TTSRawAudioOpts,
+        mut on_chunk: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnMut(Vec<f32>) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        let opts = TTSRawAudioOpts {
+            speed: opts.speed.map(clamp_speed),
+            ..opts
+        };
+        let _span = synthesis_span(&opts).entered();
+        let styles = self.styles.load_full();
+        let chunks = self.text_chunks(
+            opts.txt,
+            opts.lan,
+            opts.style_name,
+            self.init_config.speech_chunk_words,
+        );
+        let positions = chunk_positions(&chunks);
+
+        if opts.initial_silence_ms.is_some() {
+            on_chunk(self.silence(opts.initial_silence_ms))?;
+        }
+        for (chunk_index, (chunk, position)) in chunks.iter().zip(positions).enumerate() {
+            let previous = chunk_index.checked_sub(1).map(|i| &chunks[i]);
+            let chunk_audio =
+                match self.synthesize_text_chunk(chunk, previous, position, &opts, &styles) {
+                    Ok((chunk_audio, _)) => chunk_audio,
+                    Err(source) if self.init_config.skip_failed_chunks => {
+                        skip_chunk(chunk_index, &chunk.0, source);
+                        continue;
+                    }
+                    Err(source) => return Err(source),
+                };
+            on_chunk(chunk_audio)?;
+        }
 
-            let tokens = vec![padded_tokens];
+        Ok(())
+    }
 
-            match self.model.lock().unwrap().infer(
-                tokens,
-                styles.clone(),
-                speed,
-                request_id,
-                instance_id,
-                chunk_number,
-            ) {
-                Ok(chunk_audio) => {
-                    let chunk_audio: Vec<f32> = chunk_audio.iter().cloned().collect();
-                    final_audio.extend_from_slice(&chunk_audio);
+    /// Synthesize text one sentence at a time, yielding each sentence with its audio in order,
+    /// e.g. to highlight the sentence being played. Each sentence is only synthesized when
+    /// the iterator reaches it. The first sentence's audio includes the initial silence.
+    ///
+    /// Sentences failing to synthesize are yielded as errors, and the iterator goes on with
+    /// the next one, or leaves them out with a warning when
+    /// [`InitConfig::skip_failed_chunks`] is set.
+    pub fn tts_by_sentence<'a>(
+        &'a self,
+        opts: TTSRawAudioOpts<'a>,
+    ) -> impl Iterator<Item = Result<(String, Vec<f32>), TTSError>> + 'a {
+        let opts = TTSRawAudioOpts {
+            speed: opts.speed.map(clamp_speed),
+            ..opts
+        };
+        let styles = self.styles.load_full();
+        let sentences: Vec<(String, bool)> = self
+            .text_chunks(opts.txt, opts.lan, opts.style_name, None)
+            .into_iter()
+            .flat_map(|(chunk, emphasized)| {
+                split_sentences(&chunk)
+                    .into_iter()
+                    .map(|sentence| (sentence.to_string(), emphasized))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let positions = chunk_positions(&sentences);
+        let mut initial_silence = Some(self.silence(opts.initial_silence_ms));
+
+        (0..sentences.len()).filter_map(move |index| {
+            let sentence = &sentences[index];
+            let previous = index.checked_sub(1).map(|i| &sentences[i]);
+            match self.synthesize_text_chunk(sentence, previous, positions[index], &opts, &styles) {
+                Ok((audio, _)) => {
+                    let mut sentence_audio = initial_silence.take().unwrap_or_default();
+                    sentence_audio.extend(audio);
+                    Some(Ok((sentence.0.clone(), sentence_audio)))
                 }
-                Err(e) => {
-                    eprintln!("Error processing chunk: {:?}", e);
-                    eprintln!("Chunk text was: {:?}", chunk);
-                    return Err(Box::new(std::io::Error::other(format!(
-                        "Chunk processing failed: {:?}",
-                        e
-                    ))));
+                Err(source) if self.init_config.skip_failed_chunks => {
+                    skip_chunk(index, &sentence.0, source);
+                    None
                 }
+                Err(source) => Some(Err(TTSError::Synthesis(source))),
             }
+        })
+    }
+
+    /// Phonemize `text` and apply the configured phoneme transform
+    fn phonemes(&self, text: &str, lan: &str) -> ESpeakResult<String> {
+        let phonemes = phonemize_with_separator(text, lan, self.init_config.phoneme_separator)?;
+        Ok(match &self.init_config.phoneme_transform {
+            Some(transform) => transform(&phonemes),
+            None => phonemes,
+        })
+    }
+
+    /// Zero samples lasting `ms` milliseconds
+    fn silence(&self, ms: Option<u32>) -> Vec<f32> {
+        let len = ms.unwrap_or(0) as u64 * self.init_config.sample_rate as u64 / 1000;
+        vec![0.0; len as usize]
+    }
+
+    /// Phonemize and run inference on a single chunk of text
+    fn synthesize_chunk(
+        &self,
+        chunk: &str,
+        position: f32,
+        opts: &TTSRawAudioOpts,
+        styles: &Styles,
+    ) -> Result<(Vec<f32>, ChunkReport), Box<dyn std::error::Error>> {
+        let chunk_lan = if self.init_config.detect_language {
+            detect_language(chunk, opts.lan)
+        } else {
+            opts.lan
+        };
+        let _span = tracing::info_span!(
+            "chunk",
+            chunk_number = opts.chunk_number,
+            lan = chunk_lan,
+            chars = chunk.len()
+        )
+        .entered();
+
+        // Convert chunk to phonemes
+        let phonemes = tracing::info_span!("phonemize")
+            .in_scope(|| self.phonemes(chunk, chunk_lan))
+            .map_err(|e| TTSError::Phonemize {
+                text: chunk.to_string(),
+                lan: chunk_lan.to_string(),
+                message: e.to_string(),
+            })?;
+        let debug_prefix = format_debug_prefix(opts.request_id, opts.instance_id);
+        let chunk_info = opts
+            .chunk_number
+            .map(|n| format!("Chunk: {}, ", n))
+            .unwrap_or_default();
+        tracing::debug!(
+            "{} {}text: '{}' ({}) -> phonemes: '{}'",
+            debug_prefix,
+            chunk_info,
+            chunk,
+            chunk_lan,
+            phonemes
+        );
+        let mut tokens = tracing::info_span!("tokenize").in_scope(|| tokenize(&phonemes));
+
+        for _ in 0..opts.initial_silence.unwrap_or(0) {
+            tokens.insert(0, 30);
+        }
+
+        // Chunking keeps well below this, but phonemization can expand text unpredictably,
+        // and the model fails on longer inputs with an opaque shape error
+        let padding = self.init_config.padding;
+        if tokens.len() > padding.max_tokens() {
+            return Err(format!(
+                "chunk is {} tokens long (including {} silence tokens), more than the {} the \
+                 model accepts with {} pad tokens on each side: '{}'",
+                tokens.len(),
+                opts.initial_silence.unwrap_or(0),
+                padding.max_tokens(),
+                padding.count,
+                chunk
+            )
+            .into());
         }
 
-        Ok(final_audio)
+        // Get style vectors once
+        let styles = match opts.style_name.split_once('>') {
+            Some((from, to)) => {
+                morph_styles(styles, from, to, position, tokens.len(), self.blending())?
+            }
+            None => mix_styles(styles, opts.style_name, tokens.len(), self.blending())?,
+        };
+
+        let token_count = tokens.len();
+        let tokens = vec![pad_tokens(&tokens, self.init_config.padding)];
+
+        let profile = self.init_config.voice_profiles.get(opts.style_name);
+        let speed = model_speed(self.request_speed(opts), profile.pitch);
+
+        // Includes waiting for other requests' chunks, which release the lock between chunks
+        let inference_start = std::time::Instant::now();
+        let chunk_audio = self
+            .model
+            .lock()
+            .unwrap()
+            .infer(
+                tokens,
+                styles,
+                speed,
+                opts.request_id,
+                opts.instance_id,
+                opts.chunk_number,
+            )
+            .map_err(|e| format!("Chunk processing failed: {:?}", e))?;
+        let inference_ms = inference_start.elapsed().as_secs_f64() * 1000.0;
+        let chunk_audio: Vec<f32> = chunk_audio.iter().cloned().collect();
+
+        let chunk_audio = if profile.pitch == 1.0 {
+            chunk_audio
+        } else {
+            let sample_rate = self.init_config.sample_rate;
+            let shifted_rate = (sample_rate as f32 * profile.pitch).round() as u32;
+            resample(&chunk_audio, shifted_rate, sample_rate)
+        };
+        let report = ChunkReport {
+            text_len: chunk.chars().count(),
+            token_count,
+            sample_count: chunk_audio.len(),
+            inference_ms,
+        };
+        Ok((chunk_audio, report))
     }
 
     pub fn tts(
@@ -381,9 +1035,11 @@ impl TTSKoko {
             lan,
             style_name,
             save_path,
-            mono,
+            channels,
             speed,
             initial_silence,
+            initial_silence_ms,
+            format,
         }: TTSOpts,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let audio = self.tts_raw_audio_opts(TTSRawAudioOpts {
@@ -392,120 +1048,1564 @@ impl TTSKoko {
             style_name,
             speed,
             initial_silence,
+            initial_silence_ms,
             request_id: None,
             instance_id: None,
             chunk_number: None,
         })?;
 
-        // Save to file
-        if mono {
-            let spec = hound::WavSpec {
-                channels: 1,
-                sample_rate: self.init_config.sample_rate,
-                bits_per_sample: 32,
-                sample_format: hound::SampleFormat::Float,
-            };
-
-            let mut writer = hound::WavWriter::create(save_path, spec)?;
-            for &sample in &audio {
-                writer.write_sample(sample)?;
-            }
-            writer.finalize()?;
-        } else {
-            let spec = hound::WavSpec {
-                channels: 2,
-                sample_rate: self.init_config.sample_rate,
-                bits_per_sample: 32,
-                sample_format: hound::SampleFormat::Float,
-            };
-
-            let mut writer = hound::WavWriter::create(save_path, spec)?;
-            for &sample in &audio {
-                writer.write_sample(sample)?;
-                writer.write_sample(sample)?;
-            }
-            writer.finalize()?;
-        }
-        eprintln!("Audio saved to {}", save_path);
-        Ok(())
+        self.write_audio(&audio, save_path, channels, format)
     }
 
-    pub fn mix_styles(
+    /// Like [`TTSKoko::tts`] for WAV output, but writes each chunk to the file as soon as it is
+    /// synthesized, so memory use is bounded by one chunk rather than the whole text.
+    ///
+    /// Returns the level of the audio written, counting samples per channel. If synthesis
+    /// fails part way, the chunks written so far are kept as a valid WAV file.
+    pub fn tts_wav_streaming(
         &self,
-        style_name: &str,
-        tokens_len: usize,
-    ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
-        if !style_name.contains("+") {
-            if let Some(style) = self.styles.get(style_name) {
-                let styles = vec![style[tokens_len][0].to_vec()];
-                Ok(styles)
-            } else {
-                Err(format!("can not found from styles_map: {}", style_name).into())
-            }
-        } else {
-            eprintln!("parsing style mix");
-            let styles: Vec<&str> = style_name.split('+').collect();
-
-            let mut style_names = Vec::new();
-            let mut style_portions = Vec::new();
-
-            for style in styles {
-                if let Some((name, portion)) = style.split_once('.')
-                    && let Ok(portion) = portion.parse::<f32>()
-                {
-                    style_names.push(name);
-                    style_portions.push(portion * 0.1);
-                }
-            }
-            eprintln!("styles: {:?}, portions: {:?}", style_names, style_portions);
-
-            let mut blended_style = vec![vec![0.0; 256]; 1];
+        TTSOpts {
+            txt,
+            lan,
+            style_name,
+            save_path,
+            channels,
+            speed,
+            initial_silence,
+            initial_silence_ms,
+            format,
+        }: TTSOpts,
+    ) -> Result<Levels, Box<dyn std::error::Error>> {
+        if format != AudioFormat::Wav {
+            return Err(format!("streaming output only supports wav, not {}", format).into());
+        }
 
-            for (name, portion) in style_names.iter().zip(style_portions.iter()) {
-                if let Some(style) = self.styles.get(*name) {
-                    let style_slice = &style[tokens_len][0]; // This is a [256] array
-                    // Blend into the blended_style
-                    for (j, &value) in style_slice.iter().enumerate().take(256) {
-                        blended_style[0][j] += value * portion;
+        let mut writer = hound::WavWriter::create(save_path, self.wav_spec(channels))?;
+        let mut levels = Levels::default();
+        let result = self.tts_raw_audio_streaming(
+            TTSRawAudioOpts {
+                txt,
+                lan,
+                style_name,
+                speed,
+                initial_silence,
+                initial_silence_ms,
+                request_id: None,
+                instance_id: None,
+                chunk_number: None,
+            },
+            |chunk| {
+                for &sample in &chunk {
+                    for _ in 0..channels {
+                        writer.write_sample(sample)?;
                     }
                 }
-            }
-            Ok(blended_style)
-        }
+                levels.add(&chunk);
+                Ok(())
+            },
+        );
+        // Finalize even on failure so the header matches the samples already written
+        writer.finalize()?;
+        result?;
+
+        eprintln!("Audio saved to {}", save_path);
+        Ok(levels)
     }
 
-    fn load_voices(voices_path: &str) -> HashMap<String, Vec<[[f32; 256]; 1]>> {
-        let mut npz = NpzReader::new(File::open(voices_path).unwrap()).unwrap();
-        let mut map = HashMap::new();
+    /// Like [`TTSKoko::tts_wav_streaming`], but writes to `out`, which need not be seekable,
+    /// e.g. stdout. The WAV header is written first with the lengths left unknown, see
+    /// [`wav_stream`].
+    pub fn tts_wav_streaming_to<W: Write>(
+        &self,
+        opts: TTSRawAudioOpts,
+        channels: u16,
+        out: &mut W,
+    ) -> Result<Levels, Box<dyn std::error::Error>> {
+        wav_stream::write_header(out, self.init_config.sample_rate, channels)?;
+        let mut levels = Levels::default();
+        self.tts_raw_audio_streaming(opts, |chunk| {
+            wav_stream::write_samples(out, &chunk, channels)?;
+            out.flush()?;
+            levels.add(&chunk);
+            Ok(())
+        })?;
+        Ok(levels)
+    }
 
-        for voice in npz.names().unwrap() {
-            let voice_data: Result<Array3<f32>, _> = npz.by_name(&voice);
-            let voice_data = voice_data.unwrap();
-            let mut tensor = vec![[[0.0; 256]; 1]; 511];
-            for (i, inner_value) in voice_data.outer_iter().enumerate() {
-                for (j, inner_inner_value) in inner_value.outer_iter().enumerate() {
-                    for (k, number) in inner_inner_value.iter().enumerate() {
-                        tensor[i][j][k] = *number;
-                    }
-                }
+    /// Write samples to `save_path` in the given format.
+    ///
+    /// G.711 formats are resampled to 8 kHz mono and written in a WAV container when the
+    /// path ends in `.wav`, or as headerless bytes otherwise.
+    pub fn write_audio(
+        &self,
+        audio: &[f32],
+        save_path: &str,
+        channels: u16,
+        format: AudioFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!("encode", %format, samples = audio.len()).entered();
+        let law = match format {
+            AudioFormat::Wav => return self.write_wav(audio, save_path, channels),
+            AudioFormat::WavF16 => {
+                let mut file = BufWriter::new(File::create(save_path)?);
+                self.encode_wav_f16(&mut file, audio, channels)?;
+                file.flush()?;
+                eprintln!("Audio saved to {}", save_path);
+                return Ok(());
             }
-            map.insert(voice, tensor);
-        }
-
-        // Sort voices for consistent ordering
-        let _sorted_voices = {
-            let mut voices = map.keys().collect::<Vec<_>>();
-            voices.sort();
-            voices
+            AudioFormat::OggFlac => {
+                let mut file = BufWriter::new(File::create(save_path)?);
+                ogg_flac::write_to(
+                    &mut file,
+                    audio,
+                    self.init_config.sample_rate,
+                    channels,
+                    self.init_config.quantization,
+                )?;
+                file.flush()?;
+                eprintln!("Audio saved to {}", save_path);
+                return Ok(());
+            }
+            AudioFormat::Ulaw => Law::MuLaw,
+            AudioFormat::Alaw => Law::ALaw,
         };
 
-        map
+        let telephony = resample(audio, self.init_config.sample_rate, g711::SAMPLE_RATE);
+        let wav_container = Path::new(save_path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        g711::write(
+            save_path,
+            &g711::encode(&telephony, law, self.init_config.quantization),
+            law,
+            wav_container,
+        )?;
+        eprintln!("Audio saved to {}", save_path);
+        Ok(())
     }
 
-    // Returns a sorted list of available voice names
-    pub fn get_available_voices(&self) -> Vec<String> {
-        let mut voices: Vec<String> = self.styles.keys().cloned().collect();
-        voices.sort();
-        voices
+    /// Synthesize text and return it as an in-memory WAV file, for example for an HTTP response.
+    ///
+    /// G.711 formats are resampled to 8 kHz mono and always wrapped in a WAV container.
+    pub fn tts_wav_bytes(
+        &self,
+        opts: TTSRawAudioOpts,
+        channels: u16,
+        format: AudioFormat,
+    ) -> Result<Vec<u8>, TTSError> {
+        let audio = self.tts_raw_audio_opts(opts).map_err(TTSError::Synthesis)?;
+        self.encode_audio(&audio, channels, format)
+    }
+
+    /// Encode samples as WAV bytes in the given format
+    pub fn encode_audio(
+        &self,
+        audio: &[f32],
+        channels: u16,
+        format: AudioFormat,
+    ) -> Result<Vec<u8>, TTSError> {
+        let _span = tracing::info_span!("encode", %format, samples = audio.len()).entered();
+        let mut bytes = Cursor::new(Vec::new());
+        let law = match format {
+            AudioFormat::Wav => {
+                self.encode_wav(&mut bytes, audio, channels)?;
+                return Ok(bytes.into_inner());
+            }
+            AudioFormat::WavF16 => {
+                self.encode_wav_f16(&mut bytes, audio, channels)?;
+                return Ok(bytes.into_inner());
+            }
+            AudioFormat::OggFlac => {
+                ogg_flac::write_to(
+                    &mut bytes,
+                    audio,
+                    self.init_config.sample_rate,
+                    channels,
+                    self.init_config.quantization,
+                )
+                .map_err(|e| TTSError::Encoding(e.to_string()))?;
+                return Ok(bytes.into_inner());
+            }
+            AudioFormat::Ulaw => Law::MuLaw,
+            AudioFormat::Alaw => Law::ALaw,
+        };
+
+        let telephony = resample(audio, self.init_config.sample_rate, g711::SAMPLE_RATE);
+        g711::write_to(
+            &mut bytes,
+            &g711::encode(&telephony, law, self.init_config.quantization),
+            law,
+            true,
+        )?;
+        Ok(bytes.into_inner())
+    }
+
+    /// Append samples to an existing WAV file written by [`TTSKoko::write_wav`] with the same
+    /// channel count, or create it if it does not exist yet
+    pub fn append_wav(
+        &self,
+        audio: &[f32],
+        save_path: &str,
+        channels: u16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !Path::new(save_path).exists() {
+            return self.write_wav(audio, save_path, channels);
+        }
+
+        let mut writer = hound::WavWriter::append(save_path)?;
+        if writer.spec() != self.wav_spec(channels) {
+            return Err(format!(
+                "Cannot append to {}: its format {:?} differs from the output format {:?}",
+                save_path,
+                writer.spec(),
+                self.wav_spec(channels)
+            )
+            .into());
+        }
+        for &sample in audio {
+            for _ in 0..channels {
+                writer.write_sample(sample)?;
+            }
+        }
+        writer.finalize()?;
+        eprintln!("Audio appended to {}", save_path);
+        Ok(())
+    }
+
+    /// Write samples to a 32-bit float WAV file, copying them into each of `channels` channels
+    pub fn write_wav(
+        &self,
+        audio: &[f32],
+        save_path: &str,
+        channels: u16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = BufWriter::new(File::create(save_path)?);
+        self.encode_wav(&mut file, audio, channels)?;
+        eprintln!("Audio saved to {}", save_path);
+        Ok(())
+    }
+
+    /// 32-bit float WAV format at the model's sample rate
+    fn wav_spec(&self, channels: u16) -> hound::WavSpec {
+        hound::WavSpec {
+            channels,
+            sample_rate: self.init_config.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        }
+    }
+
+    fn encode_wav<W: Write + Seek>(
+        &self,
+        out: W,
+        audio: &[f32],
+        channels: u16,
+    ) -> Result<(), hound::Error> {
+        let mut writer = hound::WavWriter::new(out, self.wav_spec(channels))?;
+        for &sample in audio {
+            for _ in 0..channels {
+                writer.write_sample(sample)?;
+            }
+        }
+        writer.finalize()
+    }
+
+    fn encode_wav_f16<W: Write>(
+        &self,
+        out: &mut W,
+        audio: &[f32],
+        channels: u16,
+    ) -> Result<(), std::io::Error> {
+        let encoded = wav_f16::encode(audio, channels);
+        wav_f16::write_to(out, &encoded, self.init_config.sample_rate, channels)
+    }
+
+    pub fn mix_styles(
+        &self,
+        style_name: &str,
+        tokens_len: usize,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        mix_styles(&self.styles.load(), style_name, tokens_len, self.blending())
+    }
+
+    /// Replace the loaded voices with the contents of another voices file.
+    ///
+    /// The swap is atomic: requests started before it finish with the old voices, later
+    /// ones see the new voices. On error the current voices are kept.
+    pub fn reload_voices(&self, voices_path: &str) -> Result<(), TTSError> {
+        let styles = Self::load_voices(voices_path, self.init_config.voices_filter.as_deref())?;
+        tracing::info!("Reloaded {} voices from {}", styles.len(), voices_path);
+        self.styles.store(Arc::new(styles));
+        self.voices_source
+            .store(Arc::new(absolute_path(voices_path)));
+        Ok(())
+    }
+
+    /// Make `style`, e.g. one computed by a [`crate::model::ReferenceEncoder`], available as
+    /// voice `name` alongside the voices from the voices file, replacing any voice of that
+    /// name. The same style is used for chunks of every length. Reloading the voices file
+    /// removes it again
+    pub fn add_voice(&self, name: &str, style: [f32; 256]) {
+        self.styles.rcu(|styles| {
+            let mut styles = Styles::clone(styles);
+            styles.insert(name.to_string(), vec![[style]]);
+            styles
+        });
+    }
+
+    /// Like [`TTSKoko::reload_voices`], but takes the contents of a voices file, e.g. one
+    /// received over the network. `source` names it in log and error messages
+    pub fn reload_voices_from_bytes(&self, bytes: &[u8], source: &str) -> Result<(), TTSError> {
+        let styles = Self::parse_voices(bytes, source, self.init_config.voices_filter.as_deref())?;
+        tracing::info!("Reloaded {} voices from {}", styles.len(), source);
+        self.styles.store(Arc::new(styles));
+        self.voices_source.store(Arc::new(source.to_string()));
+        Ok(())
+    }
+
+    fn load_voices(voices_path: &str, filter: Option<&[String]>) -> Result<Styles, TTSError> {
+        let bytes = std::fs::read(voices_path)
+            .map_err(|e| TTSError::Voices(format!("{}: {}", voices_path, e)))?;
+        Self::parse_voices(&bytes, voices_path, filter)
+    }
+
+    /// Decode a voices file, as safetensors if `source` ends in `.safetensors` and as an
+    /// NPZ archive otherwise. With a `filter`, only the voices it names are decoded
+    fn parse_voices(
+        bytes: &[u8],
+        source: &str,
+        filter: Option<&[String]>,
+    ) -> Result<Styles, TTSError> {
+        let keep = |voice: &str| filter.is_none_or(|filter| filter.iter().any(|v| v == voice));
+        let safetensors = Path::new(source)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("safetensors"));
+        let styles = if safetensors {
+            Self::parse_voices_safetensors(bytes, source, keep)?
+        } else {
+            Self::parse_voices_npz(bytes, source, keep)?
+        };
+
+        if let Some(filter) = filter {
+            if styles.is_empty() {
+                return Err(TTSError::Voices(format!(
+                    "{}: none of the voices {} were found",
+                    source,
+                    filter.join(", ")
+                )));
+            }
+            for voice in filter.iter().filter(|voice| !styles.contains_key(*voice)) {
+                tracing::warn!("Voice {} is not in {}", voice, source);
+            }
+        }
+        Ok(styles)
+    }
+
+    /// Decode an NPZ voices archive, one voice per thread, each with its own reader
+    fn parse_voices_npz(
+        bytes: &[u8],
+        source: &str,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Styles, TTSError> {
+        let voices_error = |e: &dyn fmt::Display| format!("{}: {}", source, e);
+        let open = || NpzReader::new(Cursor::new(bytes)).map_err(|e| voices_error(&e));
+        let names = open()
+            .and_then(|mut npz| npz.names().map_err(|e| voices_error(&e)))
+            .map_err(TTSError::Voices)?;
+
+        names
+            .into_iter()
+            .filter(|voice| keep(voice))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|voice| {
+                let voice_data: Array3<f32> =
+                    open()?.by_name(&voice).map_err(|e| voices_error(&e))?;
+                if voice_data.shape()[1..] != [1, 256] || voice_data.is_empty() {
+                    return Err(voices_error(&format!(
+                        "voice {} has shape {:?}, expected (n, 1, 256)",
+                        voice,
+                        voice_data.shape()
+                    )));
+                }
+                let voice_data = voice_data.as_standard_layout();
+                let values = voice_data.as_slice().unwrap_or_default();
+                let tensor = values
+                    .chunks_exact(256)
+                    .map(|row| [row.try_into().unwrap()])
+                    .collect::<Vec<_>>();
+                Ok((voice, tensor))
+            })
+            .collect::<Result<Styles, String>>()
+            .map_err(TTSError::Voices)
+    }
+
+    /// Decode a safetensors voices file holding one little-endian `f32` tensor of shape
+    /// `(n, 1, 256)` per voice, the same layout as the NPZ archive
+    fn parse_voices_safetensors(
+        bytes: &[u8],
+        source: &str,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Styles, TTSError> {
+        let voices_error = |e: &dyn fmt::Display| TTSError::Voices(format!("{}: {}", source, e));
+        let tensors = SafeTensors::deserialize(bytes).map_err(|e| voices_error(&e))?;
+
+        tensors
+            .tensors()
+            .into_iter()
+            .filter(|(voice, _)| keep(voice))
+            .map(|(voice, view)| {
+                if view.dtype() != Dtype::F32 {
+                    return Err(voices_error(&format!(
+                        "voice {} has dtype {}, expected F32",
+                        voice,
+                        view.dtype()
+                    )));
+                }
+                if view.shape().len() != 3 || view.shape()[1..] != [1, 256] || view.shape()[0] == 0
+                {
+                    return Err(voices_error(&format!(
+                        "voice {} has shape {:?}, expected (n, 1, 256)",
+                        voice,
+                        view.shape()
+                    )));
+                }
+                let values: Vec<f32> = view
+                    .data()
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect();
+                let tensor = values
+                    .chunks_exact(256)
+                    .map(|row| [row.try_into().unwrap()])
+                    .collect::<Vec<_>>();
+                Ok((voice, tensor))
+            })
+            .collect()
+    }
+
+    /// Absolute path of the model file that was loaded, after searching the standard locations
+    pub fn model_resolved_path(&self) -> &str {
+        &self.model_resolved_path
+    }
+
+    /// Absolute path of the voices file currently in use, or the source name passed to
+    /// [`TTSKoko::reload_voices_from_bytes`]
+    pub fn voices_resolved_path(&self) -> String {
+        self.voices_source.load().to_string()
+    }
+
+    /// Name of the ONNX Runtime execution provider the model runs on
+    pub fn execution_provider(&self) -> &'static str {
+        self.model.lock().unwrap().execution_provider()
+    }
+
+    /// Roughly estimate how many seconds of audio `txt` will produce, without running the
+    /// model. Only phonemization is done, so this is cheap enough to call before deciding
+    /// whether to synthesize at all. It assumes a fixed, unmeasured length per phoneme
+    /// token, so treat it as a ballpark figure. Pad tokens, `initial_silence` and the speed set by
+    /// voice profiles are left out; `speed` is taken as given
+    pub fn estimate_duration(&self, txt: &str, lan: &str, speed: f32) -> f32 {
+        let tokens = txt
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| tokenize(&self.phonemes(line, lan).unwrap_or_default()).len())
+            .sum::<usize>();
+        token_duration(tokens, speed)
+    }
+
+    fn blending(&self) -> Blending {
+        Blending {
+            normalize: self.init_config.normalize_blends,
+            strict: self.init_config.strict_blends,
+        }
+    }
+
+    /// Sample rate of the synthesized audio in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.init_config.sample_rate
+    }
+
+    /// How samples are converted for the integer output formats
+    pub fn quantization(&self) -> Quantization {
+        self.init_config.quantization
+    }
+
+    // Returns a sorted list of available voice names
+    pub fn get_available_voices(&self) -> Vec<String> {
+        let mut voices: Vec<String> = self.styles.load().keys().cloned().collect();
+        voices.sort();
+        voices
+    }
+
+    /// Available voices, sorted by name, with the language and gender their names indicate
+    pub fn get_voice_metadata(&self) -> Vec<VoiceInfo> {
+        self.get_available_voices()
+            .iter()
+            .map(|name| VoiceInfo::from_name(name))
+            .collect()
+    }
+
+    /// Speed used for `style_name` when a request gives none: the speed of its voice
+    /// profile, or 1.0 without one. An explicit speed replaces it rather than being scaled
+    pub fn voice_speed(&self, style_name: &str) -> f32 {
+        self.init_config.voice_profiles.get(style_name).speed
+    }
+
+    /// The speed requested in `opts`, or the voice's own, see [`TTSKoko::voice_speed`]
+    fn request_speed(&self, opts: &TTSRawAudioOpts) -> f32 {
+        opts.speed
+            .unwrap_or_else(|| self.voice_speed(opts.style_name))
+    }
+
+    /// Check that every voice `style_name` names, including each voice of a blend or
+    /// morph, is loaded, so that a typo in a default voice fails at startup rather than
+    /// on the first request
+    pub fn check_voices(&self, style_name: &str) -> Result<(), TTSError> {
+        let styles = self.styles.load();
+        let missing: Vec<String> = style_voices(style_name)
+            .into_iter()
+            .filter(|voice| !styles.contains_key(*voice))
+            .map(|voice| voice_not_found(&styles, voice))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(TTSError::Voices(format!(
+                "{} in {} (style {})",
+                missing.join(", "),
+                self.voices_resolved_path(),
+                style_name
+            )))
+        }
+    }
+}
+
+/// Estimated seconds of speech for `tokens` phoneme tokens at `speed`, see
+/// [`TTSKoko::estimate_duration`]
+fn token_duration(tokens: usize, speed: f32) -> f32 {
+    tokens as f32 * SECONDS_PER_TOKEN / speed.clamp(MIN_SPEED, MAX_SPEED)
+}
+
+/// `speed` limited to [`MIN_SPEED`]..=[`MAX_SPEED`], warning when it is out of range.
+/// A speed that is not a number is replaced with 1.0
+pub fn clamp_speed(speed: f32) -> f32 {
+    let clamped = if speed.is_nan() {
+        1.0
+    } else {
+        speed.clamp(MIN_SPEED, MAX_SPEED)
+    };
+    if clamped != speed {
+        tracing::warn!(
+            "Speed {} is outside {}..={}, using {}",
+            speed,
+            MIN_SPEED,
+            MAX_SPEED,
+            clamped
+        );
+    }
+    clamped
+}
+
+/// Where a missing model or voices file was looked for, and where to download it
+fn not_found_message(path: &str, file_name: &str, url: &str) -> String {
+    let locations: Vec<String> = std::iter::once(path.to_string())
+        .chain(
+            TTSKoko::standard_data_dirs()
+                .into_iter()
+                .map(|dir| dir.join(file_name).display().to_string()),
+        )
+        .collect();
+    format!(
+        "file not found at any of {}. Download it from {} and place it at one of them",
+        locations.join(", "),
+        url
+    )
+}
+
+/// `path` made absolute for diagnostics, or unchanged if it cannot be resolved
+fn absolute_path(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// The names of the voices in `style_name`, e.g. `af_sarah` and `af_nicole` for
+/// `af_sarah.4+af_nicole.6`, or the voices on both sides of a `>` morph
+pub fn style_voices(style_name: &str) -> Vec<&str> {
+    style_name
+        .split('>')
+        .flat_map(|style| match style.contains('+') {
+            true => style
+                .split('+')
+                .map(|voice| voice.split_once('.').map_or(voice, |(name, _)| name))
+                .collect(),
+            false => vec![style],
+        })
+        .collect()
+}
+
+/// How blends of several voices are computed
+#[derive(Debug, Clone, Copy, Default)]
+struct Blending {
+    /// See [`InitConfig::normalize_blends`]
+    normalize: bool,
+    /// See [`InitConfig::strict_blends`]
+    strict: bool,
+}
+
+/// The loaded voice spelled most like `name`, if any is close enough to be a likely typo
+fn closest_voice<'a>(styles_map: &'a Styles, name: &str) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    styles_map
+        .keys()
+        .map(|voice| (strsim::levenshtein(name, voice), voice))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, voice)| voice.as_str())
+}
+
+/// `voice not found`, suggesting the closest loaded voice
+fn voice_not_found(styles_map: &Styles, name: &str) -> String {
+    match closest_voice(styles_map, name) {
+        Some(closest) => format!("voice {} not found, did you mean {}?", name, closest),
+        None => format!("voice {} not found", name),
+    }
+}
+
+/// Look up or blend the style vector for `style_name`, e.g. `af_sarah.4+af_nicole.6`
+fn mix_styles(
+    styles_map: &Styles,
+    style_name: &str,
+    tokens_len: usize,
+    blending: Blending,
+) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    if !style_name.contains("+") {
+        if let Some(style) = styles_map.get(style_name) {
+            let styles = vec![style_frame(style, tokens_len).to_vec()];
+            Ok(styles)
+        } else {
+            Err(voice_not_found(styles_map, style_name).into())
+        }
+    } else {
+        eprintln!("parsing style mix");
+        let styles: Vec<&str> = style_name.split('+').collect();
+
+        let mut style_names = Vec::new();
+        let mut style_portions = Vec::new();
+
+        for style in styles {
+            if let Some((name, portion)) = style.split_once('.')
+                && let Ok(portion) = portion.parse::<f32>()
+            {
+                style_names.push(name);
+                style_portions.push(portion * 0.1);
+            }
+        }
+        eprintln!("styles: {:?}, portions: {:?}", style_names, style_portions);
+
+        let mut blended_style = vec![vec![0.0; 256]; 1];
+        let mut blended_portions = 0.0;
+
+        for (name, portion) in style_names.iter().zip(style_portions.iter()) {
+            if let Some(style) = styles_map.get(*name) {
+                let style_slice = style_frame(style, tokens_len);
+                // Blend into the blended_style
+                for (j, &value) in style_slice.iter().enumerate().take(256) {
+                    blended_style[0][j] += value * portion;
+                }
+                blended_portions += portion;
+            } else if blending.strict {
+                return Err(format!(
+                    "{} in blend {}",
+                    voice_not_found(styles_map, name),
+                    style_name
+                )
+                .into());
+            } else {
+                tracing::warn!(
+                    "{} in blend {}, leaving it out",
+                    voice_not_found(styles_map, name),
+                    style_name
+                );
+            }
+        }
+        if blending.normalize && blended_portions > 0.0 {
+            for value in &mut blended_style[0] {
+                *value /= blended_portions;
+            }
+        }
+        Ok(blended_style)
+    }
+}
+
+/// Blend from the style `from` to the style `to`, each of which may itself be a mix, with
+/// `position` running from 0 (all `from`) to 1 (all `to`)
+fn morph_styles(
+    styles_map: &Styles,
+    from: &str,
+    to: &str,
+    position: f32,
+    tokens_len: usize,
+    blending: Blending,
+) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    let from = mix_styles(styles_map, from, tokens_len, blending)?;
+    let to = mix_styles(styles_map, to, tokens_len, blending)?;
+    let position = position.clamp(0.0, 1.0);
+    Ok(vec![
+        from[0]
+            .iter()
+            .zip(&to[0])
+            .map(|(a, b)| a + (b - a) * position)
+            .collect(),
+    ])
+}
+
+/// Log a chunk left out of the audio, see [`InitConfig::skip_failed_chunks`]
+fn skip_chunk(chunk_index: usize, chunk_text: &str, error: Box<dyn std::error::Error>) {
+    tracing::warn!(
+        "Skipping chunk {} ({:?}) that failed: {}",
+        chunk_index,
+        chunk_text,
+        error
+    );
+}
+
+/// Where the middle of each chunk falls in the whole text, from 0 to 1, counted in words.
+/// The model takes one style vector per inference, so morphing voices changes the blend
+/// from chunk to chunk rather than from token to token
+fn chunk_positions(chunks: &[(String, bool)]) -> Vec<f32> {
+    let words: Vec<usize> = chunks
+        .iter()
+        .map(|(chunk, _)| chunk.split_whitespace().count().max(1))
+        .collect();
+    let total = words.iter().sum::<usize>() as f32;
+    let mut start = 0;
+    words
+        .iter()
+        .map(|&count| {
+            let position = (start as f32 + count as f32 / 2.0) / total;
+            start += count;
+            position
+        })
+        .collect()
+}
+
+/// The style frame for a chunk of `tokens_len` tokens. Voices hold one frame per length,
+/// so longer chunks use the last frame
+fn style_frame(style: &[[[f32; 256]; 1]], tokens_len: usize) -> &[f32; 256] {
+    &style[tokens_len.min(style.len() - 1)][0]
+}
+
+/// The speed input for the model when `speed` is requested for a voice whose profile
+/// changes the pitch by `pitch`. Raising the pitch by a factor means synthesizing that much
+/// slower, then playing the result back faster by the same factor to restore the duration.
+/// Emphasis and pitch change the already clamped request speed, so the result is clamped
+/// again
+fn model_speed(speed: f32, pitch: f32) -> f32 {
+    (speed / pitch).clamp(MIN_SPEED, MAX_SPEED)
+}
+
+/// Middle of the `window`-sample stretch of `audio` with the least energy, among those
+/// starting between `from` and `to`
+fn quietest_point(audio: &[f32], from: usize, to: usize, window: usize) -> usize {
+    let window = window.max(1);
+    let to = to.min(audio.len().saturating_sub(window));
+    if from >= to {
+        return from.min(audio.len());
+    }
+    let energy = |start: usize| -> f32 { audio[start..start + window].iter().map(|s| s * s).sum() };
+    (from..=to)
+        .step_by((window / 2).max(1))
+        .min_by(|&a, &b| energy(a).total_cmp(&energy(b)))
+        .map_or(from, |start| start + window / 2)
+}
+
+/// Scale audio by `gain`, ramping the gain in and out over 10 ms so the change does not click
+fn apply_gain(audio: &mut [f32], gain: f32, sample_rate: u32) {
+    let ramp = (sample_rate as usize / 100).min(audio.len() / 2).max(1);
+    let len = audio.len();
+    for (i, sample) in audio.iter_mut().enumerate() {
+        let edge = i.min(len - 1 - i);
+        let factor = if edge < ramp {
+            1.0 + (gain - 1.0) * edge as f32 / ramp as f32
+        } else {
+            gain
+        };
+        *sample = (*sample * factor).clamp(-1.0, 1.0);
+    }
+}
+
+/// Surround `tokens` with `padding.count` pad tokens on each side
+fn pad_tokens(tokens: &[i64], padding: Padding) -> Vec<i64> {
+    let pad = std::iter::repeat_n(padding.token, padding.count);
+    pad.clone()
+        .chain(tokens.iter().copied())
+        .chain(pad)
+        .collect()
+}
+
+/// Span covering one synthesis request, tagged with the caller's request ID
+fn synthesis_span(opts: &TTSRawAudioOpts) -> tracing::Span {
+    tracing::info_span!(
+        "synthesize",
+        request_id = opts.request_id,
+        instance_id = opts.instance_id,
+        style = opts.style_name,
+        chars = opts.txt.len()
+    )
+}
+
+/// Split text into chunks of at most `max_words` words for low-latency streaming.
+///
+/// A chunk ends at the end of a sentence. When a sentence is longer than `max_words`, it is
+/// broken after the last clause punctuation (`,` `;` `:`) that fits, or after `max_words`
+/// words when there is none. Every word of `text` ends up in exactly one chunk, in order, and
+/// nothing is added.
+pub fn split_text_into_speech_chunks(text: &str, max_words: usize) -> Vec<String> {
+    let max_words = max_words.max(1);
+    let mut chunks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+
+    for word in text.split_whitespace() {
+        current.push(word);
+        if word.ends_with(['.', '!', '?']) {
+            chunks.push(current.join(" "));
+            current.clear();
+        } else if current.len() >= max_words {
+            let split = current
+                .iter()
+                .rposition(|word| word.ends_with([',', ';', ':']))
+                .map_or(current.len(), |i| i + 1);
+            chunks.push(current[..split].join(" "));
+            current.drain(..split);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current.join(" "));
+    }
+
+    chunks
+}
+
+/// Collects text that arrives in fragments, such as the running output of live speech
+/// recognition, and hands out clauses as soon as they are complete so they can be spoken
+/// while the rest is still coming in.
+///
+/// A clause is complete at a line break, or when a word ending a sentence, or a comma or
+/// colon after at least `min_words` words, is followed by whitespace. The last word is held
+/// back until whitespace follows it, since it may still be growing.
+#[derive(Debug, Clone, Default)]
+pub struct ClauseAccumulator {
+    pending: String,
+    min_words: usize,
+}
+
+impl ClauseAccumulator {
+    pub fn new(min_words: usize) -> Self {
+        Self {
+            pending: String::new(),
+            min_words,
+        }
+    }
+
+    /// Add a fragment of text, returning the clauses it completed, if any
+    pub fn push(&mut self, fragment: &str) -> Vec<String> {
+        self.pending.push_str(fragment);
+        let mut clauses = Vec::new();
+        let mut start = 0;
+        for (i, c) in self.pending.char_indices() {
+            if !c.is_whitespace() {
+                continue;
+            }
+            let clause = self.pending[start..i].trim();
+            let complete = !clause.is_empty()
+                && (c == '\n'
+                    || ends_sentence(clause)
+                    || (clause.ends_with([',', ':'])
+                        && clause.split_whitespace().count() >= self.min_words));
+            if complete {
+                clauses.push(clause.to_string());
+                start = i;
+            }
+        }
+        self.pending.drain(..start);
+        clauses
+    }
+
+    /// The text left over once no more fragments will arrive
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.pending);
+        let rest = rest.trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    }
+}
+
+/// Forces a chunk boundary wherever it appears in the text
+const BREAK_MARKER: &str = "<break/>";
+
+/// Forces a chunk boundary when it makes up a whole line
+const BREAK_LINE: &str = "---";
+
+/// Split `text` at explicit chunk breaks, either [`BREAK_MARKER`] or a line of just
+/// [`BREAK_LINE`], dropping the breaks themselves and any empty sections
+fn split_breaks(text: &str) -> Vec<&str> {
+    let mut sections = Vec::new();
+    for part in text.split(BREAK_MARKER) {
+        let mut section_start = 0;
+        let mut line_start = 0;
+        for line in part.split_inclusive('\n') {
+            let line_end = line_start + line.len();
+            if line.trim() == BREAK_LINE {
+                sections.push(&part[section_start..line_start]);
+                section_start = line_end;
+            }
+            line_start = line_end;
+        }
+        sections.push(&part[section_start..]);
+    }
+    sections.retain(|section| !section.trim().is_empty());
+    sections
+}
+
+/// Split text into chunks of at most `max_tokens` tokens as measured by `count_tokens`,
+/// preferring sentence boundaries, then word boundaries, then splitting inside a word
+pub fn split_into_chunks<F: Fn(&str) -> usize>(
+    text: &str,
+    max_tokens: usize,
+    count_tokens: F,
+) -> Vec<String> {
+    let mut chunks = Vec::new();
+
+    // First split by sentences - using common sentence ending punctuation
+    let sentences = split_sentences(text);
+
+    let mut current_chunk = String::new();
+
+    for sentence in sentences {
+        // Make sure every sentence ends with punctuation so it is read as one
+        let sentence = if ends_sentence(sentence) {
+            sentence.to_string()
+        } else {
+            format!("{}.", sentence)
+        };
+
+        // Convert to phonemes to check token count
+        let token_count = count_tokens(&sentence);
+
+        if token_count > max_tokens {
+            // If single sentence is too long, split by words
+            let words: Vec<&str> = sentence.split_whitespace().collect();
+            let mut word_chunk = String::new();
+
+            for word in words {
+                let test_chunk = if word_chunk.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", word_chunk, word)
+                };
+
+                let test_tokens = count_tokens(&test_chunk);
+
+                if test_tokens > max_tokens {
+                    if !word_chunk.is_empty() {
+                        chunks.push(word_chunk);
+                    }
+                    // A single word can itself be too long (URLs, chemical names)
+                    let mut pieces = split_long_word(word, max_tokens, &count_tokens);
+                    word_chunk = pieces.pop().unwrap_or_default();
+                    chunks.extend(pieces);
+                } else {
+                    word_chunk = test_chunk;
+                }
+            }
+
+            if !word_chunk.is_empty() {
+                chunks.push(word_chunk);
+            }
+        } else if !current_chunk.is_empty() {
+            // Try to append to current chunk
+            let test_text = format!("{} {}", current_chunk, sentence);
+            let test_tokens = count_tokens(&test_text);
+
+            if test_tokens > max_tokens {
+                // If combining would exceed limit, start new chunk
+                chunks.push(current_chunk);
+                current_chunk = sentence;
+            } else {
+                current_chunk = test_text;
+            }
+        } else {
+            current_chunk = sentence;
+        }
+    }
+
+    // Add the last chunk if not empty
+    if !current_chunk.is_empty() {
+        chunks.push(current_chunk);
+    }
+
+    chunks
+}
+
+/// Split emphasis segments into chunks with [`split_into_chunks`], each flagged with
+/// whether it is emphasized. A segment that stops mid-sentence, like `never` in
+/// `I said *never* again.`, is not given a full stop, so the sentence keeps its intonation
+fn split_segments<F: Fn(&str) -> usize>(
+    segments: &[Segment],
+    max_tokens: usize,
+    count_tokens: F,
+) -> Vec<(String, bool)> {
+    segments
+        .iter()
+        .enumerate()
+        .flat_map(|(i, segment)| {
+            let mut chunks = split_into_chunks(&segment.text, max_tokens, &count_tokens);
+            let mid_sentence = i + 1 < segments.len() && !ends_sentence(&segment.text);
+            if mid_sentence && let Some(last) = chunks.last_mut() {
+                // Drop the full stop split_into_chunks added
+                last.pop();
+            }
+            chunks
+                .into_iter()
+                .map(move |chunk| (chunk, segment.emphasized))
+        })
+        .collect()
+}
+
+const SENTENCE_END: [char; 4] = ['.', '?', '!', ';'];
+const CLOSING_MARKS: [char; 5] = ['"', '\u{201D}', '\u{2019}', ')', ']'];
+
+/// Whether `text` ends with sentence punctuation, possibly followed by closing quotes or
+/// brackets as in `"Stop."` or `(really!)`
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end_matches(CLOSING_MARKS).ends_with(SENTENCE_END)
+}
+
+/// Split text into trimmed sentences at whitespace following `.?!;`. Punctuation inside
+/// matched quotes or brackets does not end a sentence, so `He said "Stop. Now." Then he
+/// left.` is two sentences, while `We waited (an hour. Really.) in line.` is one. If the
+/// quotes or brackets are unbalanced they are ignored.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let split = |nested: bool| {
+        let mut closers: Vec<char> = Vec::new();
+        let mut start = 0;
+        let mut sentences = Vec::new();
+        for (i, c) in text.char_indices() {
+            match c {
+                _ if closers.last() == Some(&c) => {
+                    closers.pop();
+                }
+                '"' => closers.push('"'),
+                '\u{201C}' => closers.push('\u{201D}'),
+                '(' => closers.push(')'),
+                '[' => closers.push(']'),
+                _ if c.is_whitespace()
+                    && (!nested || closers.is_empty())
+                    && ends_sentence(&text[start..i])
+                    // After `."` or `.)` the sentence goes on if the next word is lowercase
+                    && (text[start..i].ends_with(SENTENCE_END)
+                        || !text[i..]
+                            .trim_start()
+                            .starts_with(|c: char| c.is_lowercase())) =>
+                {
+                    sentences.push(&text[start..i]);
+                    start = i;
+                }
+                _ => {}
+            }
+        }
+        sentences.push(&text[start..]);
+        (sentences, closers.is_empty())
+    };
+
+    let (sentences, balanced) = split(true);
+    let sentences = if balanced { sentences } else { split(false).0 };
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Split a word into pieces of at most `max_tokens` tokens at character boundaries.
+///
+/// Each piece is the longest prefix of the remainder that fits, found by binary search to
+/// keep the number of phonemizer calls low. A word that already fits is returned whole.
+fn split_long_word<F: Fn(&str) -> usize>(
+    word: &str,
+    max_tokens: usize,
+    count_tokens: &F,
+) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = word;
+
+    while !rest.is_empty() {
+        if count_tokens(rest) <= max_tokens {
+            pieces.push(rest.to_string());
+            break;
+        }
+
+        let boundaries: Vec<usize> = rest.char_indices().map(|(i, _)| i).skip(1).collect();
+        // Always take at least one character so the loop makes progress
+        let (mut low, mut high) = (0, boundaries.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if count_tokens(&rest[..boundaries[mid - 1]]) <= max_tokens {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        let end = if low == 0 {
+            boundaries.first().copied().unwrap_or(rest.len())
+        } else {
+            boundaries[low - 1]
+        };
+
+        pieces.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xdg_data_dirs() {
+        // Unset variables keep the traditional locations
+        assert_eq!(
+            xdg_data_dirs(None, Some("/home/koko"), None),
+            vec![
+                PathBuf::from("/home/koko/.local/share/koko"),
+                PathBuf::from("/usr/local/share/koko"),
+                PathBuf::from("/usr/share/koko"),
+            ]
+        );
+
+        // Relocated data directories are honored, relative and duplicate entries skipped
+        assert_eq!(
+            xdg_data_dirs(
+                Some("/data/user"),
+                Some("/home/koko"),
+                Some("/opt/share:relative/share::/data/user:/usr/share")
+            ),
+            vec![
+                PathBuf::from("/data/user/koko"),
+                PathBuf::from("/opt/share/koko"),
+                PathBuf::from("/usr/share/koko"),
+            ]
+        );
+
+        // A relative XDG_DATA_HOME is invalid and falls back to the default
+        assert_eq!(
+            xdg_data_dirs(Some("data"), Some("/home/koko"), Some(""))[0],
+            PathBuf::from("/home/koko/.local/share/koko")
+        );
+    }
+
+    #[test]
+    fn test_split_long_word() {
+        // One token per character stands in for the phonemizer
+        let count_tokens = |text: &str| text.chars().count();
+        let word: String = "abcdefghij".repeat(60);
+
+        let chunks = split_into_chunks(&format!("Before {} after", word), 500, count_tokens);
+        assert!(chunks.iter().all(|chunk| count_tokens(chunk) <= 500));
+        assert_eq!(
+            chunks,
+            vec![
+                "Before".to_string(),
+                word[..500].to_string(),
+                format!("{} after.", &word[500..]),
+            ]
+        );
+
+        let pieces = split_long_word("ééééé", 2, &count_tokens);
+        assert_eq!(pieces, vec!["éé", "éé", "é"]);
+        assert_eq!(split_long_word("short", 10, &count_tokens), vec!["short"]);
+    }
+
+    #[test]
+    fn test_split_segments_keeps_emphasis_mid_sentence() {
+        let count_words = |text: &str| text.split_whitespace().count();
+        let segments = split_emphasis("I said *never* again.");
+        assert_eq!(
+            split_segments(&segments, 500, count_words),
+            vec![
+                ("I said".to_string(), false),
+                ("never".to_string(), true),
+                ("again.".to_string(), false),
+            ]
+        );
+
+        // Emphasis at the end of a sentence keeps its own punctuation, and the last
+        // segment is still ended as before
+        let segments = split_emphasis("Stop *now!* Then go");
+        assert_eq!(
+            split_segments(&segments, 500, count_words),
+            vec![
+                ("Stop".to_string(), false),
+                ("now!".to_string(), true),
+                ("Then go.".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("Hi there. How are you? Fine!"),
+            vec!["Hi there.", "How are you?", "Fine!"]
+        );
+        // Sentences inside quotes stay with the sentence quoting them
+        assert_eq!(
+            split_sentences(r#"He said "Stop. Now." Then he left."#),
+            vec![r#"He said "Stop. Now.""#, "Then he left."]
+        );
+        assert_eq!(
+            split_sentences(
+                "She wrote \u{201C}I said \"no. Never.\" Twice.\u{201D} Nobody replied."
+            ),
+            vec![
+                "She wrote \u{201C}I said \"no. Never.\" Twice.\u{201D}",
+                "Nobody replied."
+            ]
+        );
+        // Parenthetical asides, nested ones too
+        assert_eq!(
+            split_sentences("We waited (an hour. Maybe two [or three. Ugh.]) in line. Then rain."),
+            vec![
+                "We waited (an hour. Maybe two [or three. Ugh.]) in line.",
+                "Then rain."
+            ]
+        );
+        // Decimal points do not end sentences, and unbalanced quotes are ignored
+        assert_eq!(
+            split_sentences(r#"It costs 3.50 now. She said "wait. Go."#),
+            vec!["It costs 3.50 now.", r#"She said "wait."#, "Go."]
+        );
+    }
+
+    #[test]
+    fn test_split_breaks() {
+        assert_eq!(
+            split_breaks("One. <break/>Two.\n---\nThree.\n  ---  \n\nFour."),
+            vec!["One. ", "Two.\n", "Three.\n", "\nFour."]
+        );
+        // Only whole lines of dashes break, and breaks in a row leave no empty sections
+        assert_eq!(
+            split_breaks("Wait --- what?<break/><break/>\n---\n"),
+            vec!["Wait --- what?"]
+        );
+        assert_eq!(split_breaks("No breaks."), vec!["No breaks."]);
+    }
+
+    #[test]
+    fn test_pad_tokens() {
+        assert_eq!(pad_tokens(&[5, 6], Padding::default()), vec![0, 5, 6, 0]);
+        assert_eq!(
+            pad_tokens(
+                &[5, 6],
+                Padding {
+                    token: 16,
+                    count: 2
+                }
+            ),
+            vec![16, 16, 5, 6, 16, 16]
+        );
+        assert_eq!(
+            pad_tokens(&[5, 6], Padding { token: 0, count: 0 }),
+            vec![5, 6]
+        );
+    }
+
+    #[test]
+    fn test_padding_max_tokens() {
+        assert_eq!(Padding::default().max_tokens(), 510);
+        assert_eq!(Padding { token: 0, count: 0 }.max_tokens(), 510);
+        assert_eq!(Padding { token: 0, count: 3 }.max_tokens(), 506);
+        assert_eq!(
+            Padding {
+                token: 0,
+                count: 300
+            }
+            .max_tokens(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_split_text_into_speech_chunks() {
+        assert_eq!(
+            split_text_into_speech_chunks("One two. Three four five six seven.", 4),
+            vec!["One two.", "Three four five six", "seven."]
+        );
+        // Long sentences break after a comma when one fits
+        assert_eq!(
+            split_text_into_speech_chunks("When it rains, we stay inside and read", 4),
+            vec!["When it rains,", "we stay inside and", "read"]
+        );
+    }
+
+    #[test]
+    fn test_split_text_into_speech_chunks_keeps_every_word() {
+        // No terminator: the text after the last comma is still spoken
+        assert_eq!(
+            split_text_into_speech_chunks("a, b, c", 2),
+            vec!["a, b,", "c"]
+        );
+        assert_eq!(
+            split_text_into_speech_chunks("word word word", 2),
+            vec!["word word", "word"]
+        );
+        // A trailing clause after the last sentence is kept, and nothing is appended
+        assert_eq!(
+            split_text_into_speech_chunks("It works. Mostly, anyway", 8),
+            vec!["It works.", "Mostly, anyway"]
+        );
+
+        let text = "One, two; three: four five, six seven eight nine. Ten eleven, twelve";
+        for max_words in 0..8 {
+            let chunks = split_text_into_speech_chunks(text, max_words);
+            assert_eq!(chunks.join(" "), text, "max_words {}", max_words);
+        }
+    }
+
+    #[test]
+    fn test_mix_styles_short_voice() {
+        // Voices need not have the usual 511 frames
+        let frames = |n: usize, scale: f32| {
+            (0..n)
+                .map(|i| [[i as f32 * scale; 256]])
+                .collect::<Vec<_>>()
+        };
+        let styles: Styles = [
+            ("short".to_string(), frames(3, 1.0)),
+            ("long".to_string(), frames(511, 1.0)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            mix_styles(&styles, "short", 1, Blending::default()).unwrap()[0][0],
+            1.0
+        );
+        // Chunks longer than the voice use its last frame instead of panicking
+        assert_eq!(
+            mix_styles(&styles, "short", 10, Blending::default()).unwrap()[0][0],
+            2.0
+        );
+        assert_eq!(
+            mix_styles(&styles, "long", 10, Blending::default()).unwrap()[0][0],
+            10.0
+        );
+
+        let blended = mix_styles(&styles, "short.5+long.5", 10, Blending::default()).unwrap();
+        assert!((blended[0][0] - 6.0).abs() < 1e-5);
+
+        // Over-weighted blends are louder unless normalized back to the ratio
+        let raw = mix_styles(&styles, "short.8+long.8", 10, Blending::default()).unwrap();
+        assert!((raw[0][0] - 9.6).abs() < 1e-5);
+        let normalized = mix_styles(
+            &styles,
+            "short.8+long.8",
+            10,
+            Blending {
+                normalize: true,
+                strict: false,
+            },
+        )
+        .unwrap();
+        assert!((normalized[0][0] - 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_missing_blend_voice() {
+        let styles: Styles = HashMap::from([
+            ("af_sarah".to_string(), vec![[[1.0; 256]]]),
+            ("af_nicole".to_string(), vec![[[3.0; 256]]]),
+        ]);
+        assert!(
+            voice_not_found(&styles, "af_nicol").ends_with("did you mean af_nicole?"),
+            "{}",
+            voice_not_found(&styles, "af_nicol")
+        );
+        assert_eq!(closest_voice(&styles, "am_michael"), None);
+
+        // Left out with a warning by default, an error when strict
+        let blended = mix_styles(&styles, "af_sarah.5+af_nicol.5", 1, Blending::default());
+        assert_eq!(blended.unwrap()[0][0], 0.5);
+        let strict = Blending {
+            normalize: false,
+            strict: true,
+        };
+        let error = mix_styles(&styles, "af_sarah.5+af_nicol.5", 1, strict).unwrap_err();
+        assert!(error.to_string().contains("did you mean af_nicole?"));
+    }
+
+    #[test]
+    fn test_style_voices() {
+        assert_eq!(style_voices("af_sarah"), vec!["af_sarah"]);
+        assert_eq!(
+            style_voices("af_sarah.4+af_nicole.6"),
+            vec!["af_sarah", "af_nicole"]
+        );
+        assert_eq!(
+            style_voices("af_sarah.5+af_nicole.5>am_adam"),
+            vec!["af_sarah", "af_nicole", "am_adam"]
+        );
+    }
+
+    #[test]
+    fn test_morph_styles() {
+        let styles: Styles = HashMap::from([
+            ("a".to_string(), vec![[[0.0; 256]]; 4]),
+            ("b".to_string(), vec![[[1.0; 256]]; 4]),
+        ]);
+        assert_eq!(
+            morph_styles(&styles, "a", "b", 0.0, 1, Blending::default()).unwrap()[0][0],
+            0.0
+        );
+        assert_eq!(
+            morph_styles(&styles, "a", "b", 0.25, 1, Blending::default()).unwrap()[0][0],
+            0.25
+        );
+        assert_eq!(
+            morph_styles(&styles, "a", "b", 1.0, 1, Blending::default()).unwrap()[0][0],
+            1.0
+        );
+        assert!(morph_styles(&styles, "a", "missing", 0.5, 1, Blending::default()).is_err());
+
+        let chunks = [("one two", false), ("three", false), ("four", false)]
+            .map(|(text, emphasized)| (text.to_string(), emphasized));
+        assert_eq!(chunk_positions(&chunks), vec![0.25, 0.625, 0.875]);
+    }
+
+    #[test]
+    fn test_token_duration() {
+        assert_eq!(token_duration(0, 1.0), 0.0);
+        assert!((token_duration(100, 1.0) - 8.0).abs() < 1e-4);
+        assert!((token_duration(100, 2.0) - token_duration(100, 1.0) / 2.0).abs() < 1e-4);
+        // Speeds the model would not get are clamped the same way here
+        assert_eq!(token_duration(100, 50.0), token_duration(100, MAX_SPEED));
+        assert_eq!(token_duration(100, 0.0), token_duration(100, MIN_SPEED));
+    }
+
+    #[test]
+    fn test_clamp_speed() {
+        assert_eq!(clamp_speed(1.3), 1.3);
+        assert_eq!(clamp_speed(0.0), MIN_SPEED);
+        assert_eq!(clamp_speed(-2.0), MIN_SPEED);
+        assert_eq!(clamp_speed(50.0), MAX_SPEED);
+        assert_eq!(clamp_speed(f32::NAN), 1.0);
+    }
+
+    #[test]
+    fn test_parse_voices_safetensors() {
+        let values: Vec<u8> = (0..2 * 256)
+            .flat_map(|i| (i as f32).to_le_bytes())
+            .collect();
+        let voice =
+            safetensors::tensor::TensorView::new(Dtype::F32, vec![2, 1, 256], &values).unwrap();
+        let bytes = safetensors::serialize([("af_test", voice)], None).unwrap();
+
+        let styles = TTSKoko::parse_voices(&bytes, "voices.safetensors", None).unwrap();
+        assert_eq!(styles["af_test"].len(), 2);
+        assert_eq!(styles["af_test"][1][0][0], 256.0);
+
+        let filter = ["af_other".to_string()];
+        assert!(TTSKoko::parse_voices(&bytes, "voices.safetensors", Some(&filter)).is_err());
+        let filter = ["af_test".to_string(), "af_other".to_string()];
+        let styles = TTSKoko::parse_voices(&bytes, "voices.safetensors", Some(&filter)).unwrap();
+        assert_eq!(styles.len(), 1);
+
+        let flat = safetensors::tensor::TensorView::new(Dtype::F32, vec![2, 256], &values).unwrap();
+        let bytes = safetensors::serialize([("af_test", flat)], None).unwrap();
+        assert!(TTSKoko::parse_voices(&bytes, "voices.safetensors", None).is_err());
+    }
+
+    /// Times loading an NPZ archive shaped like voices-v1.0.bin on one thread, as before
+    /// voices were decoded in parallel, and on rayon's default pool. Run with `cargo test
+    /// --release -p kokoros npz_load_time -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn test_npz_load_time() {
+        let mut npz = ndarray_npy::NpzWriter::new(Cursor::new(Vec::new()));
+        let voice = ndarray::Array3::<f32>::from_shape_fn((510, 1, 256), |(i, _, j)| {
+            (i * 256 + j) as f32 * 1e-6
+        });
+        for i in 0..54 {
+            npz.add_array(format!("voice_{:02}", i), &voice).unwrap();
+        }
+        let bytes = npz.finish().unwrap().into_inner();
+
+        let load = || {
+            let start = std::time::Instant::now();
+            let styles = TTSKoko::parse_voices(&bytes, "voices.bin", None).unwrap();
+            assert_eq!(styles.len(), 54);
+            start.elapsed()
+        };
+        let one_thread = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        // Warm up the allocator and caches
+        load();
+        let sequential = one_thread.install(load);
+        let parallel = load();
+        eprintln!(
+            "{} MB: {:?} on one thread, {:?} on {} threads",
+            bytes.len() / 1_000_000,
+            sequential,
+            parallel,
+            rayon::current_num_threads()
+        );
+    }
+
+    #[test]
+    fn test_quietest_point() {
+        let mut audio = vec![0.5; 1000];
+        audio[600..700].fill(0.0);
+        assert_eq!(quietest_point(&audio, 400, 800, 100), 650);
+        // A range past the end is clamped to the audio
+        assert_eq!(quietest_point(&audio, 2000, 3000, 100), 1000);
+    }
+
+    #[test]
+    fn test_model_speed() {
+        // The model divides phoneme durations by its speed input, so a higher requested speed
+        // must reach it as a higher value to give shorter audio
+        assert_eq!(model_speed(1.0, 1.0), 1.0);
+        assert!(model_speed(1.5, 1.0) > model_speed(1.0, 1.0));
+        assert!(model_speed(0.5, 1.0) < model_speed(1.0, 1.0));
+        // A raised pitch synthesizes slower, to be sped up again by resampling
+        assert_eq!(model_speed(1.0, 1.25), 0.8);
+
+        // An emphasized chunk at the top speed still reaches the model in range
+        let emphasis = Emphasis {
+            speed: 1.5,
+            ..Emphasis::default()
+        };
+        assert_eq!(
+            model_speed(clamp_speed(5.0) * emphasis.speed, 1.0),
+            MAX_SPEED
+        );
+        assert_eq!(model_speed(MIN_SPEED * 0.5, 1.0), MIN_SPEED);
+    }
+
+    #[test]
+    fn test_clause_accumulator() {
+        let mut clauses = ClauseAccumulator::new(3);
+        assert!(clauses.push("Hello wor").is_empty());
+        assert!(clauses.push("ld.").is_empty());
+        assert_eq!(clauses.push(" Well, how"), vec!["Hello world."]);
+        assert_eq!(clauses.push(" are you, my"), vec!["Well, how are you,"]);
+        assert_eq!(clauses.push(" friend\nagain"), vec!["my friend"]);
+        assert_eq!(clauses.finish(), Some("again".to_string()));
+        assert_eq!(clauses.finish(), None);
     }
 }
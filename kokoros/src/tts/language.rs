@@ -0,0 +1,182 @@
+use whatlang::Lang;
+
+/// Curated espeak-ng language codes with their names, from
+/// https://github.com/espeak-ng/espeak-ng/blob/master/docs/languages.md
+/// (espeak-rs offers no way to enumerate the installed voices)
+pub const ESPEAK_LANGUAGES: &[(&str, &str)] = &[
+    ("af", "Afrikaans"),
+    ("am", "Amharic"),
+    ("ar", "Arabic"),
+    ("az", "Azerbaijani"),
+    ("be", "Belarusian"),
+    ("bg", "Bulgarian"),
+    ("bn", "Bengali"),
+    ("bs", "Bosnian"),
+    ("ca", "Catalan"),
+    ("cmn", "Chinese (Mandarin)"),
+    ("cs", "Czech"),
+    ("cy", "Welsh"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en-029", "English (Caribbean)"),
+    ("en-gb", "English (Great Britain)"),
+    ("en-gb-scotland", "English (Scotland)"),
+    ("en-gb-x-rp", "English (Received Pronunciation)"),
+    ("en-us", "English (America)"),
+    ("eo", "Esperanto"),
+    ("es", "Spanish (Spain)"),
+    ("es-419", "Spanish (Latin America)"),
+    ("et", "Estonian"),
+    ("eu", "Basque"),
+    ("fa", "Persian"),
+    ("fi", "Finnish"),
+    ("fr-be", "French (Belgium)"),
+    ("fr-ch", "French (Switzerland)"),
+    ("fr-fr", "French (France)"),
+    ("ga", "Irish"),
+    ("gd", "Scottish Gaelic"),
+    ("gu", "Gujarati"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hr", "Croatian"),
+    ("hu", "Hungarian"),
+    ("hy", "Armenian"),
+    ("id", "Indonesian"),
+    ("is", "Icelandic"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ka", "Georgian"),
+    ("kn", "Kannada"),
+    ("ko", "Korean"),
+    ("la", "Latin"),
+    ("lt", "Lithuanian"),
+    ("lv", "Latvian"),
+    ("mk", "Macedonian"),
+    ("ml", "Malayalam"),
+    ("mr", "Marathi"),
+    ("ms", "Malay"),
+    ("nb", "Norwegian Bokmål"),
+    ("ne", "Nepali"),
+    ("nl", "Dutch"),
+    ("pa", "Punjabi"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese (Portugal)"),
+    ("pt-br", "Portuguese (Brazil)"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("sk", "Slovak"),
+    ("sl", "Slovenian"),
+    ("sq", "Albanian"),
+    ("sr", "Serbian"),
+    ("sv", "Swedish"),
+    ("sw", "Swahili"),
+    ("ta", "Tamil"),
+    ("te", "Telugu"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("ur", "Urdu"),
+    ("vi", "Vietnamese (Northern)"),
+    ("yue", "Chinese (Cantonese)"),
+];
+
+/// Languages the Kokoro v1.0 voices were made for
+pub const KOKORO_LANGUAGES: &[&str] = &[
+    "en-us", "en-gb", "es", "fr-fr", "hi", "it", "ja", "pt-br", "cmn",
+];
+
+/// Maps a detected language to the espeak-ng voice code used for phonemization.
+///
+/// Only languages espeak-ng can phonemize are mapped; anything else returns `None`.
+pub fn espeak_code(lang: Lang) -> Option<&'static str> {
+    let code = match lang {
+        Lang::Eng => "en-us",
+        Lang::Fra => "fr-fr",
+        Lang::Deu => "de",
+        Lang::Spa => "es",
+        Lang::Ita => "it",
+        Lang::Por => "pt-br",
+        Lang::Nld => "nl",
+        Lang::Pol => "pl",
+        Lang::Ces => "cs",
+        Lang::Slk => "sk",
+        Lang::Ron => "ro",
+        Lang::Hun => "hu",
+        Lang::Fin => "fi",
+        Lang::Swe => "sv",
+        Lang::Dan => "da",
+        Lang::Nob => "nb",
+        Lang::Tur => "tr",
+        Lang::Rus => "ru",
+        Lang::Ukr => "uk",
+        Lang::Bul => "bg",
+        Lang::Ell => "el",
+        Lang::Cat => "ca",
+        Lang::Lat => "la",
+        Lang::Epo => "eo",
+        Lang::Hin => "hi",
+        Lang::Ara => "ar",
+        Lang::Heb => "he",
+        Lang::Vie => "vi",
+        Lang::Ind => "id",
+        Lang::Jpn => "ja",
+        Lang::Kor => "ko",
+        Lang::Cmn => "cmn",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Returns the language family part of an espeak code (`en-us` -> `en`)
+fn base_code(code: &str) -> &str {
+    code.split(['-', '_']).next().unwrap_or(code)
+}
+
+/// Detects the language of a chunk of text, returning the espeak-ng code to phonemize it with.
+///
+/// Falls back to `default_lan` when detection is unreliable, maps to no espeak voice, or
+/// agrees with the default's language family (so a configured `en-gb` stays `en-gb`).
+pub fn detect_language<'a>(text: &str, default_lan: &'a str) -> &'a str {
+    let Some(info) = whatlang::detect(text) else {
+        return default_lan;
+    };
+    if !info.is_reliable() {
+        return default_lan;
+    }
+
+    match espeak_code(info.lang()) {
+        Some(code) if base_code(code) != base_code(default_lan) => code,
+        _ => default_lan,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_lists() {
+        let codes: Vec<&str> = ESPEAK_LANGUAGES.iter().map(|(code, _)| *code).collect();
+        for code in KOKORO_LANGUAGES {
+            assert!(
+                codes.contains(code),
+                "{} missing from ESPEAK_LANGUAGES",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let french = "Je ne sais pas pourquoi il est parti si tôt ce matin, mais il avait l'air très fatigué.";
+        assert_eq!(detect_language(french, "en-us"), "fr-fr");
+
+        // Same language family as the default keeps the default's regional variant
+        let english = "I have no idea why he left so early this morning, but he looked very tired.";
+        assert_eq!(detect_language(english, "en-gb"), "en-gb");
+
+        // Too short to detect reliably
+        assert_eq!(detect_language("Oui", "en-us"), "en-us");
+        assert_eq!(detect_language("", "en-us"), "en-us");
+    }
+}
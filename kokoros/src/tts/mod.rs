@@ -1,4 +1,12 @@
+pub mod frontend;
+pub mod instructions;
 pub mod koko;
+pub mod language;
 pub mod normalize;
+pub mod phoneme_rules;
+pub mod phonemizer;
+pub mod preprocess;
 pub mod tokenize;
 pub mod vocab;
+pub mod voice_info;
+pub mod voice_profile;
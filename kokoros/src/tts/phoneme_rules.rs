@@ -0,0 +1,69 @@
+use std::fs;
+
+/// Substitutions applied to espeak's phonemes before tokenization, to correct words it
+/// gets wrong consistently. Loaded from a text file with one `from -> to` rule per line:
+///
+/// ```text
+/// # "data" as "day-ta"
+/// dˈæɾə -> dˈeɪɾə
+/// ```
+///
+/// Rules are applied in file order, each replacing every occurrence. Blank lines and lines
+/// starting with `#` are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct PhonemeRules {
+    rules: Vec<(String, String)>,
+}
+
+impl PhonemeRules {
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rules = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once("->") {
+                Some((from, to)) if !from.trim().is_empty() => {
+                    rules.push((from.trim().to_string(), to.trim().to_string()));
+                }
+                _ => {
+                    return Err(format!(
+                        "phoneme rule on line {} is not `from -> to`: {}",
+                        i + 1,
+                        line
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read phoneme rules {}: {}", path, e))?;
+        Self::parse(&contents)
+    }
+
+    pub fn apply(&self, phonemes: &str) -> String {
+        self.rules
+            .iter()
+            .fold(phonemes.to_string(), |phonemes, (from, to)| {
+                phonemes.replace(from.as_str(), to)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phoneme_rules() {
+        let rules = PhonemeRules::parse("# comment\n\nab -> x\nxc -> y\nq ->\n").unwrap();
+        assert_eq!(rules.apply("abc abq"), "y x");
+        assert!(PhonemeRules::parse("no arrow here").is_err());
+        assert!(PhonemeRules::parse(" -> x").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Phonemization through a dedicated espeak-ng worker thread.
+//!
+//! espeak-rs uses global state internally and is not thread-safe, so every call has to be
+//! serialized. All requests are sent over a channel to a single thread that owns espeak.
+//! This is no faster than a global mutex, as espeak still runs one call at a time, but
+//! requests are served strictly in arrival order and the time each spent queued is logged
+//! at trace level. If the thread dies, e.g. because espeak panicked on some input, the next
+//! caller starts a new one.
+
+use espeak_rs::{ESpeakError, ESpeakResult, text_to_phonemes};
+use lazy_static::lazy_static;
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::Instant;
+
+struct Request {
+    text: String,
+    lan: String,
+    separator: Option<char>,
+    queued_at: Instant,
+    reply: mpsc::Sender<ESpeakResult<String>>,
+}
+
+/// The running worker thread, numbered so that concurrent callers who all saw it stop
+/// only start one replacement
+struct Worker {
+    generation: u64,
+    sender: mpsc::Sender<Request>,
+}
+
+lazy_static! {
+    static ref WORKER: Mutex<Worker> = Mutex::new(Worker {
+        generation: 0,
+        sender: spawn_worker(),
+    });
+}
+
+fn current_worker() -> (u64, mpsc::Sender<Request>) {
+    let worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    (worker.generation, worker.sender.clone())
+}
+
+/// Replace the worker of `generation` after it stopped, unless it was already replaced
+fn respawn(generation: u64) {
+    let mut worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    if worker.generation == generation {
+        tracing::warn!("espeak worker thread stopped, starting a new one");
+        worker.sender = spawn_worker();
+        worker.generation += 1;
+    }
+}
+
+fn spawn_worker() -> mpsc::Sender<Request> {
+    let (tx, rx) = mpsc::channel::<Request>();
+    thread::Builder::new()
+        .name("espeak".to_string())
+        .spawn(move || {
+            for request in rx {
+                let waited = request.queued_at.elapsed();
+                let started = Instant::now();
+                let phonemes =
+                    text_to_phonemes(&request.text, &request.lan, request.separator, true, false)
+                        .map(|phonemes| phonemes.join(""));
+                tracing::trace!(
+                    "espeak: queued {:?}, phonemized {} chars in {:?}",
+                    waited,
+                    request.text.len(),
+                    started.elapsed()
+                );
+                // The caller may have given up waiting, which is fine
+                let _ = request.reply.send(phonemes);
+            }
+        })
+        .expect("Failed to spawn espeak worker thread");
+    tx
+}
+
+fn worker_stopped() -> ESpeakError {
+    ESpeakError("espeak worker thread is not running".to_string())
+}
+
+/// Convert text to a phoneme string with espeak-ng, blocking until the worker has served
+/// every request queued before this one
+pub fn phonemize(text: &str, lan: &str) -> ESpeakResult<String> {
+    phonemize_with_separator(text, lan, None)
+}
+
+/// Like [`phonemize`], but with `separator` between the phonemes of each word, e.g. `_` for
+/// `h_ə_l_ˈoʊ`. The Kokoro vocab expects no separator, so only use one for inspecting the
+/// phonemes or with a model whose vocab was built the same way.
+///
+/// espeak-rs always requests IPA output, so espeak-ng's ASCII (Kirshenbaum) mode is not
+/// available here.
+pub fn phonemize_with_separator(
+    text: &str,
+    lan: &str,
+    separator: Option<char>,
+) -> ESpeakResult<String> {
+    let (reply, response) = mpsc::channel();
+    let request = Request {
+        text: text.to_string(),
+        lan: lan.to_string(),
+        separator,
+        queued_at: Instant::now(),
+        reply,
+    };
+
+    // A worker that stopped between requests is replaced and the request sent again
+    let (mut generation, mut worker) = current_worker();
+    if let Err(mpsc::SendError(request)) = worker.send(request) {
+        respawn(generation);
+        (generation, worker) = current_worker();
+        worker.send(request).map_err(|_| worker_stopped())?;
+    }
+    // One that stopped on this request is replaced for the next ones, but this request
+    // fails rather than risk stopping the new worker too
+    response.recv().map_err(|_| {
+        respawn(generation);
+        worker_stopped()
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Times `CALLERS` threads phonemizing a sentence `CALLS` times each through the worker,
+    /// and through a mutex around direct espeak calls as before the worker existed. Both
+    /// serialize espeak, so this compares throughput and the worst wait a caller sees. Run
+    /// with `cargo test --release -p kokoros phonemize_contention -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn test_phonemize_contention() {
+        const CALLERS: usize = 8;
+        const CALLS: usize = 50;
+        const TEXT: &str = "The quick brown fox jumps over the lazy dog, twice.";
+
+        /// Total time for all callers, and the longest single call
+        fn run(call: impl Fn() + Sync) -> (Duration, Duration) {
+            let start = Instant::now();
+            let longest = thread::scope(|scope| {
+                let callers: Vec<_> = (0..CALLERS)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            (0..CALLS)
+                                .map(|_| {
+                                    let start = Instant::now();
+                                    call();
+                                    start.elapsed()
+                                })
+                                .max()
+                                .unwrap()
+                        })
+                    })
+                    .collect();
+                callers
+                    .into_iter()
+                    .map(|c| c.join().unwrap())
+                    .max()
+                    .unwrap()
+            });
+            (start.elapsed(), longest)
+        }
+
+        // Warm up espeak, which loads its data on first use
+        phonemize(TEXT, "en").unwrap();
+
+        let (worker_total, worker_longest) = run(|| {
+            phonemize(TEXT, "en").unwrap();
+        });
+        let espeak_mutex = Mutex::new(());
+        let (mutex_total, mutex_longest) = run(|| {
+            let _guard = espeak_mutex.lock().unwrap();
+            text_to_phonemes(TEXT, "en", None, true, false).unwrap();
+        });
+        eprintln!(
+            "{} callers x {} calls: worker {:?} (longest call {:?}), mutex {:?} (longest call {:?})",
+            CALLERS, CALLS, worker_total, worker_longest, mutex_total, mutex_longest
+        );
+    }
+}
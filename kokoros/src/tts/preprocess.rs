@@ -0,0 +1,273 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref FENCE_RE: Regex = Regex::new(r"^\s*(```|~~~)").unwrap();
+    static ref HEADING_RE: Regex = Regex::new(r"^\s*#{1,6}\s+(.*?)(?:\s+#+)?\s*$").unwrap();
+    static ref RULE_RE: Regex = Regex::new(r"^\s*(?:[-*_]\s*){3,}$").unwrap();
+    static ref LINK_DEFINITION_RE: Regex = Regex::new(r"^\s*\[[^\]]+\]:\s*\S+").unwrap();
+    static ref BLOCKQUOTE_RE: Regex = Regex::new(r"^\s*(?:>\s?)+").unwrap();
+    static ref LIST_MARKER_RE: Regex = Regex::new(r"^\s*(?:[-*+]|\d+[.)])\s+").unwrap();
+    static ref LINK_RE: Regex = Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref REFERENCE_LINK_RE: Regex = Regex::new(r"!?\[([^\]]*)\]\[[^\]]*\]").unwrap();
+    static ref AUTOLINK_RE: Regex = Regex::new(r"<((?:https?|mailto):[^>]+)>").unwrap();
+    static ref INLINE_CODE_RE: Regex = Regex::new(r"`+([^`]*)`+").unwrap();
+    static ref STRONG_EMPHASIS_RE: Regex = Regex::new(r"\*\*\*(.+?)\*\*\*|___(.+?)___").unwrap();
+    static ref STRONG_RE: Regex = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    static ref EMPHASIS_RE: Regex =
+        Regex::new(r"\*(\S(?:[^*]*\S)?)\*|(?:^|\b)_(\S(?:[^_]*\S)?)_(?:\b|$)").unwrap();
+    static ref STRIKETHROUGH_RE: Regex = Regex::new(r"~~(.+?)~~").unwrap();
+    static ref EMPHASIS_MARKUP_RE: Regex =
+        Regex::new(r"(?s)\[\[emphasis\]\](.*?)\[\[/emphasis\]\]|\*([^\s*](?:[^*]*[^\s*])?)\*")
+            .unwrap();
+}
+
+/// Punctuation after which speech pauses. It is kept after its spoken name so the pause remains
+const PAUSE_PUNCTUATION: &[char] = &[',', '.', '!', '?', ';', ':'];
+
+/// Spoken names of punctuation marks for a language, by its two-letter prefix
+fn punctuation_names(lan: &str) -> &'static [(char, &'static str)] {
+    match lan.split(['-', '_']).next().unwrap_or_default() {
+        "es" => &[
+            (',', "coma"),
+            ('.', "punto"),
+            ('!', "signo de exclamación"),
+            ('?', "signo de interrogación"),
+            (';', "punto y coma"),
+            (':', "dos puntos"),
+            ('(', "abre paréntesis"),
+            (')', "cierra paréntesis"),
+            ('"', "comillas"),
+            ('-', "guion"),
+        ],
+        "fr" => &[
+            (',', "virgule"),
+            ('.', "point"),
+            ('!', "point d'exclamation"),
+            ('?', "point d'interrogation"),
+            (';', "point-virgule"),
+            (':', "deux-points"),
+            ('(', "parenthèse ouvrante"),
+            (')', "parenthèse fermante"),
+            ('"', "guillemets"),
+            ('-', "tiret"),
+        ],
+        "de" => &[
+            (',', "Komma"),
+            ('.', "Punkt"),
+            ('!', "Ausrufezeichen"),
+            ('?', "Fragezeichen"),
+            (';', "Semikolon"),
+            (':', "Doppelpunkt"),
+            ('(', "Klammer auf"),
+            (')', "Klammer zu"),
+            ('"', "Anführungszeichen"),
+            ('-', "Bindestrich"),
+        ],
+        _ => &[
+            (',', "comma"),
+            ('.', "period"),
+            ('!', "exclamation mark"),
+            ('?', "question mark"),
+            (';', "semicolon"),
+            (':', "colon"),
+            ('(', "open parenthesis"),
+            (')', "close parenthesis"),
+            ('"', "quote"),
+            ('-', "dash"),
+        ],
+    }
+}
+
+/// Replace punctuation with its spoken name in language `lan`, so "Hi, you." is read as
+/// "Hi comma, you period.". English, Spanish, French and German are supported; other
+/// languages get the English names. Decimal points, thousands separators and hyphens inside
+/// words are left alone.
+pub fn speak_punctuation(text: &str, lan: &str) -> String {
+    let names = punctuation_names(lan);
+    let chars: Vec<char> = text.chars().collect();
+    let mut spoken = String::with_capacity(text.len() * 2);
+
+    for (i, &c) in chars.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1).copied();
+        let between =
+            |f: fn(&char) -> bool| prev.as_ref().is_some_and(f) && next.as_ref().is_some_and(f);
+        let in_number = matches!(c, '.' | ',') && between(char::is_ascii_digit);
+        let in_word = c == '-' && between(|c| c.is_alphanumeric());
+
+        match names.iter().find(|(mark, _)| *mark == c) {
+            Some((_, name)) if !in_number && !in_word => {
+                spoken.push(' ');
+                spoken.push_str(name);
+                if PAUSE_PUNCTUATION.contains(&c) {
+                    spoken.push(c);
+                }
+                spoken.push(' ');
+            }
+            _ => spoken.push(c),
+        }
+    }
+
+    spoken.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A run of input text, either spoken normally or emphasized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub emphasized: bool,
+}
+
+/// Split text on emphasis markup, `*word*` or `[[emphasis]]some words[[/emphasis]]`,
+/// dropping the markers. Unmatched markers are left in the text as they are.
+pub fn split_emphasis(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut push = |text: &str, emphasized: bool| {
+        if !text.trim().is_empty() {
+            segments.push(Segment {
+                text: text.trim().to_string(),
+                emphasized,
+            });
+        }
+    };
+
+    let mut last = 0;
+    for caps in EMPHASIS_MARKUP_RE.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        push(&text[last..whole.start()], false);
+        let inner = caps.get(1).or_else(|| caps.get(2)).unwrap();
+        push(inner.as_str(), true);
+        last = whole.end();
+    }
+    push(&text[last..], false);
+    segments
+}
+
+/// Strip Markdown syntax so only the prose is spoken.
+///
+/// Headings, emphasis, inline code and link markup are reduced to their text, while fenced
+/// code blocks, horizontal rules and link reference definitions are dropped entirely. Every
+/// input line maps to one output line (possibly empty), so line numbers are preserved.
+pub fn markdown_to_text(markdown: &str) -> String {
+    let mut in_fence = false;
+    let lines: Vec<String> = markdown
+        .lines()
+        .map(|line| {
+            if FENCE_RE.is_match(line) {
+                in_fence = !in_fence;
+                return String::new();
+            }
+            if in_fence || RULE_RE.is_match(line) || LINK_DEFINITION_RE.is_match(line) {
+                return String::new();
+            }
+            strip_line(line)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn strip_line(line: &str) -> String {
+    let line = match HEADING_RE.captures(line) {
+        // End headings with a full stop so they are read as a sentence of their own
+        Some(caps) => {
+            let heading = caps[1].trim_end();
+            if heading.ends_with(['.', '!', '?', ':']) {
+                heading.to_string()
+            } else {
+                format!("{}.", heading)
+            }
+        }
+        None => line.to_string(),
+    };
+
+    let line = BLOCKQUOTE_RE.replace(&line, "");
+    let line = LIST_MARKER_RE.replace(&line, "");
+    let line = LINK_RE.replace_all(&line, "$1");
+    let line = REFERENCE_LINK_RE.replace_all(&line, "$1");
+    let line = AUTOLINK_RE.replace_all(&line, "$1");
+    let line = INLINE_CODE_RE.replace_all(&line, "$1");
+    let line = STRONG_EMPHASIS_RE.replace_all(&line, "$1$2");
+    let line = STRONG_RE.replace_all(&line, "$1$2");
+    let line = EMPHASIS_RE.replace_all(&line, "$1$2");
+    let line = STRIKETHROUGH_RE.replace_all(&line, "$1");
+    line.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_text_inline() {
+        assert_eq!(markdown_to_text("## Getting started"), "Getting started.");
+        assert_eq!(markdown_to_text("# Why? #"), "Why?");
+        assert_eq!(
+            markdown_to_text("This is **bold**, *italic*, __strong__ and _em_ text."),
+            "This is bold, italic, strong and em text."
+        );
+        assert_eq!(
+            markdown_to_text("See [the docs](https://example.com/docs) and ![a logo](logo.png)."),
+            "See the docs and a logo."
+        );
+        assert_eq!(
+            markdown_to_text("Run `cargo build` first."),
+            "Run cargo build first."
+        );
+        assert_eq!(markdown_to_text("- first item"), "first item");
+        assert_eq!(markdown_to_text("> quoted ~~not~~"), "quoted not");
+        // Underscores inside words are not emphasis
+        assert_eq!(markdown_to_text("snake_case_name"), "snake_case_name");
+    }
+
+    #[test]
+    fn test_markdown_to_text_blocks() {
+        let markdown = "# Title\n\nIntro.\n\n```sh\ncargo run\n```\n\n---\n[docs]: https://example.com\nOutro.";
+        let text = markdown_to_text(markdown);
+        assert_eq!(text.lines().count(), markdown.lines().count());
+        assert_eq!(text, "Title.\n\nIntro.\n\n\n\n\n\n\n\nOutro.");
+    }
+
+    #[test]
+    fn test_speak_punctuation() {
+        assert_eq!(
+            speak_punctuation("Hello, world. It costs 3.50, right?", "en-us"),
+            "Hello comma, world period. It costs 3.50 comma, right question mark?"
+        );
+        assert_eq!(
+            speak_punctuation("A well-known (old) trick.", "en-gb"),
+            "A well-known open parenthesis old close parenthesis trick period."
+        );
+        assert_eq!(
+            speak_punctuation("Hola, amigo.", "es"),
+            "Hola coma, amigo punto."
+        );
+    }
+
+    #[test]
+    fn test_split_emphasis() {
+        let plain = |text: &str| Segment {
+            text: text.to_string(),
+            emphasized: false,
+        };
+        let stressed = |text: &str| Segment {
+            text: text.to_string(),
+            emphasized: true,
+        };
+
+        assert_eq!(
+            split_emphasis("I said *never* again."),
+            vec![plain("I said"), stressed("never"), plain("again.")]
+        );
+        assert_eq!(
+            split_emphasis("[[emphasis]]Stop right there[[/emphasis]], please."),
+            vec![stressed("Stop right there"), plain(", please.")]
+        );
+        assert_eq!(
+            split_emphasis("No markup here."),
+            vec![plain("No markup here.")]
+        );
+        // A lone asterisk or one followed by a space is not markup
+        assert_eq!(split_emphasis("5 * 3 = 15*"), vec![plain("5 * 3 = 15*")]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Language and gender of Kokoro voices, from their naming convention.
+//!
+//! Voice names start with a language letter and a gender letter followed by an underscore,
+//! e.g. `af_sarah` is an American English female voice and `bm_george` a British male one.
+
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Female,
+    Male,
+}
+
+impl fmt::Display for Gender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Gender::Female => "female",
+            Gender::Male => "male",
+        })
+    }
+}
+
+/// A voice and what its name says about it. Fields are `None` for names that do not follow
+/// the convention, such as custom voices
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VoiceInfo {
+    pub name: String,
+    /// espeak-ng code of the language the voice was made for, usable as `lan`
+    pub language: Option<&'static str>,
+    pub gender: Option<Gender>,
+}
+
+impl VoiceInfo {
+    pub fn from_name(name: &str) -> Self {
+        let prefix = match name.as_bytes() {
+            [language, gender, b'_', ..] => Some((*language, *gender)),
+            _ => None,
+        };
+        Self {
+            name: name.to_string(),
+            language: prefix.and_then(|(language, _)| prefix_language(language)),
+            gender: prefix.and_then(|(_, gender)| match gender {
+                b'f' => Some(Gender::Female),
+                b'm' => Some(Gender::Male),
+                _ => None,
+            }),
+        }
+    }
+}
+
+/// Language of a voice name's first letter, one of
+/// [`KOKORO_LANGUAGES`](crate::tts::language::KOKORO_LANGUAGES)
+fn prefix_language(letter: u8) -> Option<&'static str> {
+    Some(match letter {
+        b'a' => "en-us",
+        b'b' => "en-gb",
+        b'e' => "es",
+        b'f' => "fr-fr",
+        b'h' => "hi",
+        b'i' => "it",
+        b'j' => "ja",
+        b'p' => "pt-br",
+        b'z' => "cmn",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_info() {
+        assert_eq!(
+            VoiceInfo::from_name("bm_george"),
+            VoiceInfo {
+                name: "bm_george".to_string(),
+                language: Some("en-gb"),
+                gender: Some(Gender::Male),
+            }
+        );
+        let info = VoiceInfo::from_name("zf_xiaobei");
+        assert_eq!(
+            (info.language, info.gender),
+            (Some("cmn"), Some(Gender::Female))
+        );
+
+        let unknown = VoiceInfo::from_name("xq_custom");
+        assert_eq!((unknown.language, unknown.gender), (None, None));
+        let custom = VoiceInfo::from_name("narrator");
+        assert_eq!((custom.language, custom.gender), (None, None));
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+/// Per-voice adjustments applied whenever the voice is used
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceProfile {
+    /// Multiplier on the requested speed
+    pub speed: f32,
+    /// Pitch multiplier, e.g. `1.05` raises the pitch by 5% without changing duration
+    pub pitch: f32,
+}
+
+impl Default for VoiceProfile {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            pitch: 1.0,
+        }
+    }
+}
+
+/// Voice profiles keyed by style name, loaded from a TOML file such as:
+///
+/// ```toml
+/// [af_sarah]
+/// speed = 0.95
+///
+/// [am_adam]
+/// speed = 1.1
+/// pitch = 0.97
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VoiceProfiles {
+    profiles: HashMap<String, VoiceProfile>,
+}
+
+impl VoiceProfiles {
+    pub fn from_toml_str(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let profiles: HashMap<String, VoiceProfile> = toml::from_str(contents)?;
+        for (voice, profile) in &profiles {
+            if !(profile.speed > 0.0 && profile.pitch > 0.0) {
+                return Err(format!(
+                    "voice profile '{}' must have a positive speed and pitch",
+                    voice
+                )
+                .into());
+            }
+        }
+        Ok(Self { profiles })
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read voice profiles {}: {}", path, e))?;
+        Self::from_toml_str(&contents)
+    }
+
+    /// Profile for a style name, or the neutral profile if none is configured
+    pub fn get(&self, style_name: &str) -> VoiceProfile {
+        self.profiles.get(style_name).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_profiles() {
+        let profiles = VoiceProfiles::from_toml_str(
+            "[af_sarah]\nspeed = 0.9\n\n[am_adam]\nspeed = 1.1\npitch = 0.95\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            profiles.get("af_sarah"),
+            VoiceProfile {
+                speed: 0.9,
+                pitch: 1.0
+            }
+        );
+        assert_eq!(profiles.get("am_adam").pitch, 0.95);
+        assert_eq!(profiles.get("bf_emma"), VoiceProfile::default());
+
+        assert!(VoiceProfiles::from_toml_str("[af_sarah]\nspeed = 0\n").is_err());
+        assert!(VoiceProfiles::from_toml_str("[af_sarah]\nvolume = 2\n").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! End-to-end synthesis through the real model.
+//!
+//! These need the model and voices files, which are too large to bundle. They are looked up
+//! at `KOKO_TEST_MODEL` and `KOKO_TEST_VOICES`, falling back to the paths the CLI uses by
+//! default, and the tests pass without doing anything when the files are not there.
+
+use kokoros::audio::AudioFormat;
+use kokoros::tts::koko::{TTSKoko, TTSOpts, TTSRawAudioOpts};
+use std::path::{Path, PathBuf};
+
+const TEXT: &str = "Hello from the smoke test.";
+const VOICE: &str = "af_sarah";
+
+/// Path from the environment variable, or relative to the workspace root
+fn fixture(var: &str, default: &str) -> PathBuf {
+    std::env::var_os(var).map(PathBuf::from).unwrap_or_else(|| {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join(default)
+    })
+}
+
+/// Load the model, or `None` with a note on stderr when its files are missing
+fn load() -> Option<TTSKoko> {
+    let model = fixture("KOKO_TEST_MODEL", "checkpoints/kokoro-v1.0.onnx");
+    let voices = fixture("KOKO_TEST_VOICES", "data/voices-v1.0.bin");
+    for path in [&model, &voices] {
+        if !path.exists() {
+            eprintln!(
+                "skipping smoke test: {} not found, set KOKO_TEST_MODEL and KOKO_TEST_VOICES",
+                path.display()
+            );
+            return None;
+        }
+    }
+    Some(TTSKoko::new(
+        model.to_str().unwrap(),
+        voices.to_str().unwrap(),
+    ))
+}
+
+#[test]
+fn test_raw_audio() {
+    let Some(tts) = load() else { return };
+    let audio = tts
+        .tts_raw_audio(TEXT, "en-us", VOICE, 1.0, None, None, None, None)
+        .unwrap();
+    assert!(!audio.is_empty());
+    assert!(audio.iter().all(|sample| sample.is_finite()));
+    assert!(audio.iter().any(|&sample| sample != 0.0));
+}
+
+#[test]
+fn test_speed_shortens_audio() {
+    let Some(tts) = load() else { return };
+    let normal = tts
+        .tts_raw_audio(TEXT, "en-us", VOICE, 1.0, None, None, None, None)
+        .unwrap();
+    let fast = tts
+        .tts_raw_audio(TEXT, "en-us", VOICE, 2.0, None, None, None, None)
+        .unwrap();
+    assert!(
+        fast.len() < normal.len(),
+        "speed 2.0 gave {} samples, speed 1.0 gave {}",
+        fast.len(),
+        normal.len()
+    );
+}
+
+#[test]
+fn test_by_sentence() {
+    let Some(tts) = load() else { return };
+    let sentences: Vec<(String, Vec<f32>)> = tts
+        .tts_by_sentence(TTSRawAudioOpts {
+            txt: "First sentence. And the second one!",
+            lan: "en-us",
+            style_name: VOICE,
+            speed: None,
+            initial_silence: None,
+            initial_silence_ms: None,
+            request_id: None,
+            instance_id: None,
+            chunk_number: None,
+        })
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let texts: Vec<&str> = sentences.iter().map(|(text, _)| text.as_str()).collect();
+    assert_eq!(texts, ["First sentence.", "And the second one!"]);
+    assert!(sentences.iter().all(|(_, audio)| !audio.is_empty()));
+}
+
+#[test]
+fn test_wav_file() {
+    let Some(tts) = load() else { return };
+    let path = std::env::temp_dir().join(format!("kokoros-smoke-{}.wav", std::process::id()));
+    tts.tts(TTSOpts {
+        txt: TEXT,
+        lan: "en-us",
+        style_name: VOICE,
+        save_path: path.to_str().unwrap(),
+        channels: 1,
+        speed: None,
+        initial_silence: None,
+        initial_silence_ms: None,
+        format: AudioFormat::Wav,
+    })
+    .unwrap();
+
+    let reader = hound::WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    let samples = reader.len();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(spec.channels, 1);
+    assert_eq!(spec.sample_rate, 24000);
+    assert!(samples > 0);
+}
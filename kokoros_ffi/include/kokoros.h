@@ -0,0 +1,56 @@
+#ifndef KOKOROS_H
+#define KOKOROS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define KOKOROS_OK 0
+#define KOKOROS_ERR_NULL_ARGUMENT 1
+#define KOKOROS_ERR_INVALID_UTF8 2
+#define KOKOROS_ERR_SYNTHESIS 3
+#define KOKOROS_ERR_BUFFER_TOO_SMALL 4
+#define KOKOROS_ERR_VOICES 5
+
+typedef struct KokorosHandle KokorosHandle;
+
+/*
+ * Load the model and voices file. Returns NULL on failure, with the reason
+ * available from kokoros_last_error.
+ */
+KokorosHandle *kokoros_create(const char *model_path, const char *voices_path);
+
+/* Free a handle. Passing NULL is a no-op. */
+void kokoros_free(KokorosHandle *handle);
+
+/* Sample rate of the synthesized audio in Hz, or 0 for a NULL handle. */
+uint32_t kokoros_sample_rate(const KokorosHandle *handle);
+
+/*
+ * Replace the handle's voices with another voices file. Calls already in progress
+ * finish with the old voices. On failure the old voices are kept.
+ */
+int kokoros_reload_voices(const KokorosHandle *handle, const char *voices_path);
+
+/*
+ * Synthesize text into a caller-provided buffer of mono float samples.
+ * The produced sample count is written to out_len. When the buffer is too
+ * small, nothing is copied, out_len receives the required length and
+ * KOKOROS_ERR_BUFFER_TOO_SMALL is returned. The audio is kept until then, so
+ * repeating the call with the same arguments only copies it.
+ */
+int kokoros_synthesize(const KokorosHandle *handle, const char *text,
+                       const char *lan, const char *style, float speed,
+                       float *buffer, size_t buffer_len, size_t *out_len);
+
+/* Last error message on the calling thread, or NULL. */
+const char *kokoros_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KOKOROS_H */
@@ -0,0 +1,376 @@
+//! C ABI for embedding Kokoros in other languages.
+//!
+//! See `include/kokoros.h` for the matching C declarations.
+
+use kokoros::tts::koko::{InitConfig, TTSKoko, TTSRawAudioOpts};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_float, c_int};
+use std::ptr;
+use std::sync::Mutex;
+
+pub const KOKOROS_OK: c_int = 0;
+pub const KOKOROS_ERR_NULL_ARGUMENT: c_int = 1;
+pub const KOKOROS_ERR_INVALID_UTF8: c_int = 2;
+pub const KOKOROS_ERR_SYNTHESIS: c_int = 3;
+pub const KOKOROS_ERR_BUFFER_TOO_SMALL: c_int = 4;
+pub const KOKOROS_ERR_VOICES: c_int = 5;
+
+/// Opaque handle to a loaded model and voices file
+pub struct KokorosHandle {
+    tts: TTSKoko,
+    /// Audio that did not fit the caller's buffer, kept so the retry only copies it
+    pending: Mutex<Option<Pending>>,
+}
+
+/// A finished synthesis and the arguments it was made with
+struct Pending {
+    text: String,
+    lan: String,
+    style: String,
+    speed: f32,
+    audio: Vec<f32>,
+}
+
+impl Pending {
+    fn matches(&self, text: &str, lan: &str, style: &str, speed: f32) -> bool {
+        self.text == text && self.lan == lan && self.style == style && self.speed == speed
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_else(|_| c"invalid error message".into());
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Borrow a C string as `&str`, recording an error if it is null or not UTF-8
+unsafe fn str_arg<'a>(value: *const c_char, name: &str) -> Result<&'a str, c_int> {
+    if value.is_null() {
+        set_last_error(format!("{} must not be null", name));
+        return Err(KOKOROS_ERR_NULL_ARGUMENT);
+    }
+    // SAFETY: the caller guarantees `value` is a valid NUL-terminated string
+    unsafe { CStr::from_ptr(value) }.to_str().map_err(|_| {
+        set_last_error(format!("{} is not valid UTF-8", name));
+        KOKOROS_ERR_INVALID_UTF8
+    })
+}
+
+/// Load the model and voices file. Returns null on failure, with the reason available
+/// from `kokoros_last_error`.
+///
+/// # Safety
+/// `model_path` and `voices_path` must be valid NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_create(
+    model_path: *const c_char,
+    voices_path: *const c_char,
+) -> *mut KokorosHandle {
+    let (model_path, voices_path) = match unsafe {
+        (
+            str_arg(model_path, "model_path"),
+            str_arg(voices_path, "voices_path"),
+        )
+    } {
+        (Ok(model_path), Ok(voices_path)) => (model_path, voices_path),
+        _ => return ptr::null_mut(),
+    };
+
+    match TTSKoko::try_from_config(model_path, voices_path, InitConfig::default()) {
+        Ok(tts) => Box::into_raw(Box::new(KokorosHandle {
+            tts,
+            pending: Mutex::new(None),
+        })),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free a handle created by `kokoros_create`. Passing null is a no-op.
+///
+/// # Safety
+/// `handle` must come from `kokoros_create` and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_free(handle: *mut KokorosHandle) {
+    if !handle.is_null() {
+        // SAFETY: the caller guarantees the handle came from `kokoros_create`
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// Sample rate of the synthesized audio in Hz, or 0 for a null handle.
+///
+/// # Safety
+/// `handle` must be null or a live handle from `kokoros_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_sample_rate(handle: *const KokorosHandle) -> u32 {
+    // SAFETY: the caller guarantees the handle is null or live
+    match unsafe { handle.as_ref() } {
+        Some(handle) => handle.tts.sample_rate(),
+        None => 0,
+    }
+}
+
+/// Replace the voices of a handle with another voices file. Calls already in progress
+/// finish with the old voices; on failure the old voices are kept.
+///
+/// # Safety
+/// `handle` must be a live handle from `kokoros_create` and `voices_path` a valid
+/// NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kokoros_reload_voices(
+    handle: *const KokorosHandle,
+    voices_path: *const c_char,
+) -> c_int {
+    // SAFETY: the caller guarantees the handle is null or live
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        set_last_error("handle must not be null".to_string());
+        return KOKOROS_ERR_NULL_ARGUMENT;
+    };
+    let voices_path = match unsafe { str_arg(voices_path, "voices_path") } {
+        Ok(path) => path,
+        Err(code) => return code,
+    };
+
+    match handle.tts.reload_voices(voices_path) {
+        Ok(()) => KOKOROS_OK,
+        Err(e) => {
+            set_last_error(e.to_string());
+            KOKOROS_ERR_VOICES
+        }
+    }
+}
+
+/// Synthesize `text` into the caller-provided buffer of `buffer_len` mono f32 samples.
+///
+/// The number of samples produced is written to `out_len`. If the buffer is too small,
+/// nothing is copied, `out_len` receives the required length and
+/// `KOKOROS_ERR_BUFFER_TOO_SMALL` is returned, so the call can be repeated with a larger
+/// buffer. The audio is kept until then, so repeating the call with the same arguments
+/// only copies it. `buffer` may be null when `buffer_len` is 0.
+///
+/// # Safety
+/// `handle` must be a live handle from `kokoros_create`, the string arguments must be valid
+/// NUL-terminated strings, `buffer` must be valid for `buffer_len` writes and `out_len`
+/// must be valid for a single write.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn kokoros_synthesize(
+    handle: *const KokorosHandle,
+    text: *const c_char,
+    lan: *const c_char,
+    style: *const c_char,
+    speed: c_float,
+    buffer: *mut c_float,
+    buffer_len: usize,
+    out_len: *mut usize,
+) -> c_int {
+    if out_len.is_null() || (buffer.is_null() && buffer_len > 0) {
+        set_last_error("buffer and out_len must not be null".to_string());
+        return KOKOROS_ERR_NULL_ARGUMENT;
+    }
+    let args = unsafe {
+        (
+            str_arg(text, "text"),
+            str_arg(lan, "lan"),
+            str_arg(style, "style"),
+        )
+    };
+    let (txt, lan, style_name) = match args {
+        (Ok(text), Ok(lan), Ok(style)) => (text, lan, style),
+        (Err(code), _, _) | (_, Err(code), _) | (_, _, Err(code)) => return code,
+    };
+    // SAFETY: the caller guarantees the handle is null or live
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        set_last_error("handle must not be null".to_string());
+        return KOKOROS_ERR_NULL_ARGUMENT;
+    };
+
+    // Not held during synthesis, so calls on other threads are not serialized
+    let cached = handle
+        .pending
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .filter(|pending| pending.matches(txt, lan, style_name, speed));
+    let synthesized = match cached {
+        Some(pending) => Ok(pending.audio),
+        None => handle.tts.tts_raw_audio_opts(TTSRawAudioOpts {
+            txt,
+            lan,
+            style_name,
+            speed: Some(speed),
+            initial_silence: None,
+            initial_silence_ms: None,
+            request_id: None,
+            instance_id: None,
+            chunk_number: None,
+        }),
+    };
+    let audio = match synthesized {
+        Ok(audio) => audio,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return KOKOROS_ERR_SYNTHESIS;
+        }
+    };
+
+    // SAFETY: checked for null above, the caller guarantees it is writable
+    unsafe { *out_len = audio.len() };
+    if audio.len() > buffer_len {
+        set_last_error(format!(
+            "buffer holds {} samples but {} are needed",
+            buffer_len,
+            audio.len()
+        ));
+        *handle.pending.lock().unwrap_or_else(|e| e.into_inner()) = Some(Pending {
+            text: txt.to_string(),
+            lan: lan.to_string(),
+            style: style_name.to_string(),
+            speed,
+            audio,
+        });
+        return KOKOROS_ERR_BUFFER_TOO_SMALL;
+    }
+    if !audio.is_empty() {
+        // SAFETY: the caller guarantees `buffer` is valid for `buffer_len` >= `audio.len()` writes
+        unsafe { ptr::copy_nonoverlapping(audio.as_ptr(), buffer, audio.len()) };
+    }
+    KOKOROS_OK
+}
+
+/// Message describing the last error on this thread, or null if there was none.
+///
+/// The pointer stays valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn kokoros_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let message = kokoros_last_error();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_no_error_yet() {
+        // Errors are per thread, so a new thread starts without one
+        std::thread::spawn(|| assert!(kokoros_last_error().is_null()))
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_failures() {
+        let handle = unsafe { kokoros_create(ptr::null(), c"voices.bin".as_ptr()) };
+        assert!(handle.is_null());
+        assert_eq!(last_error(), "model_path must not be null");
+
+        let invalid = c"\xff.onnx";
+        let handle = unsafe { kokoros_create(invalid.as_ptr(), c"voices.bin".as_ptr()) };
+        assert!(handle.is_null());
+        assert_eq!(last_error(), "model_path is not valid UTF-8");
+
+        let handle = unsafe {
+            kokoros_create(
+                c"/nonexistent/kokoros-ffi-missing.onnx".as_ptr(),
+                c"voices.bin".as_ptr(),
+            )
+        };
+        assert!(handle.is_null());
+        assert!(last_error().contains("kokoros-ffi-missing.onnx"));
+    }
+
+    #[test]
+    fn test_null_handle() {
+        unsafe { kokoros_free(ptr::null_mut()) };
+        assert_eq!(unsafe { kokoros_sample_rate(ptr::null()) }, 0);
+
+        let code = unsafe { kokoros_reload_voices(ptr::null(), c"voices.bin".as_ptr()) };
+        assert_eq!(code, KOKOROS_ERR_NULL_ARGUMENT);
+        assert_eq!(last_error(), "handle must not be null");
+
+        let mut out_len = 0;
+        let code = unsafe {
+            kokoros_synthesize(
+                ptr::null(),
+                c"Hello".as_ptr(),
+                c"en-us".as_ptr(),
+                c"af_sky".as_ptr(),
+                1.0,
+                ptr::null_mut(),
+                0,
+                &mut out_len,
+            )
+        };
+        assert_eq!(code, KOKOROS_ERR_NULL_ARGUMENT);
+        assert_eq!(last_error(), "handle must not be null");
+    }
+
+    #[test]
+    fn test_synthesize_argument_errors() {
+        let mut buffer = [0.0f32; 4];
+        let code = unsafe {
+            kokoros_synthesize(
+                ptr::null(),
+                c"Hello".as_ptr(),
+                c"en-us".as_ptr(),
+                c"af_sky".as_ptr(),
+                1.0,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(code, KOKOROS_ERR_NULL_ARGUMENT);
+        assert_eq!(last_error(), "buffer and out_len must not be null");
+
+        let mut out_len = 0;
+        let code = unsafe {
+            kokoros_synthesize(
+                ptr::null(),
+                c"Hello".as_ptr(),
+                c"en-us".as_ptr(),
+                c"\xffsky".as_ptr(),
+                1.0,
+                ptr::null_mut(),
+                0,
+                &mut out_len,
+            )
+        };
+        assert_eq!(code, KOKOROS_ERR_INVALID_UTF8);
+        assert_eq!(last_error(), "style is not valid UTF-8");
+
+        let code = unsafe {
+            kokoros_synthesize(
+                ptr::null(),
+                ptr::null(),
+                c"en-us".as_ptr(),
+                c"af_sky".as_ptr(),
+                1.0,
+                ptr::null_mut(),
+                0,
+                &mut out_len,
+            )
+        };
+        assert_eq!(code, KOKOROS_ERR_NULL_ARGUMENT);
+        assert_eq!(last_error(), "text must not be null");
+    }
+}